@@ -1,6 +1,7 @@
 use crate::cli;
+use colored::Colorize;
 use serde::Deserialize;
-use serde_json::Error as JsonError;
+use serde_json::{Error as JsonError, Value};
 use std::env;
 use std::io;
 use std::process::{Command, Output, Stdio};
@@ -23,6 +24,12 @@ pub enum SubcommandError {
 
     #[error("JSON error: {1}")]
     Json(#[source] JsonError, String),
+
+    #[error("No executable artifact for package(s): {}", .0.join(", "))]
+    PackageArtifact(Vec<String>),
+
+    #[error("Build produced no executable, check that the crate has a `[[bin]]` target")]
+    NoArtifact,
 }
 
 trait Runner {
@@ -40,17 +47,30 @@ impl Runner for Command {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Debug)]
 pub(crate) struct CargoArtifact {
     pub(crate) executable: String,
+    pub(crate) package_id: Option<String>,
     pub(crate) target: CargoArtifactTarget,
 }
 
+#[derive(Deserialize, Debug)]
+struct CargoArtifactMessage {
+    executable: Option<String>,
+    package_id: Option<String>,
+    target: CargoArtifactTarget,
+}
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct CargoArtifactTarget {
     pub(crate) name: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct BuildScriptMessage {
+    linked_libs: Vec<String>,
+}
+
 #[derive(Deserialize, Debug)]
 struct CargoMessage {
     message: Option<CargoMessageMessage>,
@@ -61,44 +81,43 @@ struct CargoMessageMessage {
     rendered: String,
 }
 
-pub(crate) fn run(args: &cli::BuildArgs, verbose: usize) -> Result<CargoArtifact, SubcommandError> {
+pub(crate) fn run(
+    args: &cli::BuildArgs,
+    verbose: usize,
+) -> Result<Vec<CargoArtifact>, SubcommandError> {
     // Add -Clinker-plugin-lto if necessary
-    let rustflags = env::var("RUSTFLAGS")
-        .map(|mut var| {
-            var.push_str(" -Clinker-plugin-lto");
-            var
-        })
-        .or_else(|e| match e {
-            env::VarError::NotPresent => Ok(String::from("-Clinker-plugin-lto")),
-            e => Err(e),
-        })?;
+    let lto = !args.no_lto && is_release(args);
+    let rustflags = rustflags(env::var("RUSTFLAGS"), lto)?;
+    if verbose > 0 {
+        eprintln!("{}", rustflags_log(&rustflags));
+    }
     env::set_var("RUSTFLAGS", rustflags);
 
-    // Add --release flag if necessary
-    let build_args = {
-        let release_flag = "--release".to_owned();
-
-        let mut args = args.rest.clone();
-        if !args.contains(&release_flag) {
-            args.push(release_flag);
-        }
-        args
-    };
-
     let output = Command::new("cargo")
         .arg(format!("+{}", include_str!("../rust-toolchain").trim()))
         .arg("build")
         .arg("-Z=build-std=core,alloc")
         .arg("--message-format=json-render-diagnostics")
         .arg(format!("--target={}", args.target.as_ref().unwrap()))
-        .args(build_args)
+        .args(build_args(args))
         .stderr(Stdio::inherit())
         .run(verbose)?;
 
     let json = String::from_utf8(output.stdout)?;
+
+    // C archives are usually compiled without LTO bitcode
+    let libs = linked_libs(&json);
+    if lto && !libs.is_empty() {
+        eprintln!(
+            "{} Build scripts link C libraries with LTO enabled, use `--no-lto` if linking fails: {}",
+            "warning:".yellow().bold(),
+            libs.join(", ")
+        );
+    }
+
     if output.status.success() {
         // Successful build
-        parse_artifact(&json)
+        filter_packages(parse_artifacts(&json)?, &args.package)
     } else {
         // Failed build
         let (_artifacts, errors) = split_output(&json);
@@ -108,26 +127,176 @@ pub(crate) fn run(args: &cli::BuildArgs, verbose: usize) -> Result<CargoArtifact
     }
 }
 
+/// Arguments for `cargo build`, the free arguments followed by those derived
+/// from `args`.
+fn build_args(args: &cli::BuildArgs) -> Vec<String> {
+    let mut build_args = args.rest.clone();
+
+    // Add --release flag if necessary, unless another profile is requested
+    let release_flag = "--release".to_owned();
+    match args.profile {
+        Some(ref profile) => build_args.push(format!("--profile={}", profile)),
+        None if !build_args.contains(&release_flag) => build_args.push(release_flag),
+        None => (),
+    }
+    if args.workspace {
+        build_args.push("--workspace".to_owned());
+    }
+    for package in &args.package {
+        build_args.push(format!("--package={}", package));
+    }
+    if !args.features.is_empty() {
+        build_args.push(format!("--features={}", args.features.join(",")));
+    }
+    if args.no_default_features {
+        build_args.push("--no-default-features".to_owned());
+    }
+    if args.all_features {
+        build_args.push("--all-features".to_owned());
+    }
+
+    build_args
+}
+
+/// Whether the build uses the release profile, the default.
+fn is_release(args: &cli::BuildArgs) -> bool {
+    args.profile
+        .as_deref()
+        .map_or(true, |profile| profile == "release")
+}
+
+/// The effective RUSTFLAGS, `existing` with `-Clinker-plugin-lto` appended when
+/// `lto` is enabled.
+fn rustflags(existing: Result<String, env::VarError>, lto: bool) -> Result<String, env::VarError> {
+    let existing = existing.or_else(|e| match e {
+        env::VarError::NotPresent => Ok(String::new()),
+        e => Err(e),
+    })?;
+    if !lto {
+        return Ok(existing);
+    }
+
+    let mut flags = existing;
+    if !flags.is_empty() {
+        flags.push(' ');
+    }
+    flags.push_str("-Clinker-plugin-lto");
+
+    Ok(flags)
+}
+
+/// Log line for the effective RUSTFLAGS, shown before invoking cargo.
+fn rustflags_log(rustflags: &str) -> String {
+    format!("+ RUSTFLAGS={:?}", rustflags)
+}
+
+/// The `reason` field of a line of cargo JSON output. Lines that are not JSON
+/// objects, like build script output, have no reason.
+fn reason(line: &str) -> Option<String> {
+    let message: Value = serde_json::from_str(line).ok()?;
+
+    message.get("reason")?.as_str().map(str::to_owned)
+}
+
+/// Split cargo JSON output into compiler artifacts and compiler messages. Any
+/// other lines are dropped.
 fn split_output(json: &str) -> (Vec<&str>, Vec<&str>) {
-    json.trim()
-        .split('\n')
-        .filter(|x| {
-            !x.is_empty()
-                && !x.starts_with('#')
-                && !x.contains("] cargo:")
-                && !x.contains(r#""reason":"build-script-executed""#)
-        })
-        .partition(|x| x.contains(r#""reason":"compiler-artifact""#))
-}
-
-fn parse_artifact(json: &str) -> Result<CargoArtifact, SubcommandError> {
+    let mut artifacts = Vec::new();
+    let mut messages = Vec::new();
+
+    for line in json.lines() {
+        match reason(line).as_deref() {
+            Some("compiler-artifact") => artifacts.push(line),
+            Some("compiler-message") => messages.push(line),
+            _ => (),
+        }
+    }
+
+    (artifacts, messages)
+}
+
+fn parse_artifacts(json: &str) -> Result<Vec<CargoArtifact>, SubcommandError> {
     // Warnings need to be handled separately
     let (artifacts, warnings) = split_output(json);
     print_messages(warnings)?;
 
-    // Return build artifact
-    let json = *artifacts.last().expect("Expected artifact JSON");
-    serde_json::from_str(json).map_err(|e| SubcommandError::Json(e, json.into()))
+    // Return all executable build artifacts, libraries have no executable
+    let mut executables = Vec::new();
+    for json in artifacts {
+        let artifact: CargoArtifactMessage =
+            serde_json::from_str(json).map_err(|e| SubcommandError::Json(e, json.into()))?;
+
+        if let Some(executable) = artifact.executable {
+            executables.push(CargoArtifact {
+                executable,
+                package_id: artifact.package_id,
+                target: artifact.target,
+            });
+        }
+    }
+    if executables.is_empty() {
+        return Err(SubcommandError::NoArtifact);
+    }
+
+    Ok(executables)
+}
+
+/// Keep only the artifacts of the requested packages, matched by target name or
+/// package name. Every package must produce at least one artifact.
+fn filter_packages(
+    artifacts: Vec<CargoArtifact>,
+    packages: &[String],
+) -> Result<Vec<CargoArtifact>, SubcommandError> {
+    if packages.is_empty() {
+        return Ok(artifacts);
+    }
+
+    let matches = |artifact: &CargoArtifact, package: &str| {
+        artifact.target.name == package
+            || artifact.package_id.as_deref().map(package_name) == Some(package)
+    };
+
+    let missing: Vec<_> = packages
+        .iter()
+        .filter(|package| !artifacts.iter().any(|a| matches(a, package)))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(SubcommandError::PackageArtifact(missing));
+    }
+
+    Ok(artifacts
+        .into_iter()
+        .filter(|a| packages.iter().any(|package| matches(a, package)))
+        .collect())
+}
+
+/// The package name within a package ID, either `name 0.1.0 (source)`,
+/// `source#name@0.1.0`, or `source#0.1.0` when the name is the last path
+/// segment of the source.
+fn package_name(package_id: &str) -> &str {
+    match package_id.split_once('#') {
+        Some((_, fragment)) if fragment.contains('@') => {
+            fragment.split('@').next().unwrap_or(fragment)
+        }
+        Some((source, _)) => {
+            let path = source.split('?').next().unwrap_or(source);
+            path.trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or(path)
+        }
+        None => package_id.split(' ').next().unwrap_or(package_id),
+    }
+}
+
+/// Collect the libraries linked by build scripts, e.g. `static=c`.
+fn linked_libs(json: &str) -> Vec<String> {
+    json.lines()
+        .filter(|x| reason(x).as_deref() == Some("build-script-executed"))
+        .filter_map(|x| serde_json::from_str::<BuildScriptMessage>(x).ok())
+        .flat_map(|message| message.linked_libs)
+        .collect()
 }
 
 fn print_messages<'a, T>(messages: T) -> Result<(), SubcommandError>
@@ -145,3 +314,164 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_args, filter_packages, is_release, linked_libs, package_name, parse_artifacts,
+        rustflags, rustflags_log, split_output, SubcommandError,
+    };
+    use crate::cli::BuildArgs;
+    use gumdrop::Options;
+    use std::env;
+
+    #[test]
+    fn test_parse_artifacts_workspace() {
+        let json = [
+            r#"{"reason":"compiler-artifact","target":{"name":"rrt0"},"executable":null}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"hello"},"executable":"/t/hello"}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"world"},"executable":"/t/world"}"#,
+        ]
+        .join("\n");
+
+        let artifacts = parse_artifacts(&json).unwrap();
+        let names: Vec<_> = artifacts.iter().map(|a| a.target.name.as_str()).collect();
+        let executables: Vec<_> = artifacts.iter().map(|a| a.executable.as_str()).collect();
+
+        assert_eq!(names, ["hello", "world"]);
+        assert_eq!(executables, ["/t/hello", "/t/world"]);
+    }
+
+    #[test]
+    fn test_parse_artifacts_none() {
+        let json = [
+            r#"{"reason":"compiler-message","message":{"rendered":"warning: unused\n"}}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"rrt0"},"executable":null}"#,
+        ]
+        .join("\n");
+
+        assert!(matches!(
+            parse_artifacts(&json),
+            Err(SubcommandError::NoArtifact)
+        ));
+        assert!(matches!(
+            parse_artifacts(""),
+            Err(SubcommandError::NoArtifact)
+        ));
+    }
+
+    #[test]
+    fn test_rustflags_log() {
+        let flags = rustflags(Err(env::VarError::NotPresent), true).unwrap();
+        assert_eq!(
+            rustflags_log(&flags),
+            r#"+ RUSTFLAGS="-Clinker-plugin-lto""#
+        );
+
+        let flags = rustflags(Ok("-Copt-level=s".into()), true).unwrap();
+        assert_eq!(
+            rustflags_log(&flags),
+            r#"+ RUSTFLAGS="-Copt-level=s -Clinker-plugin-lto""#
+        );
+
+        let flags = rustflags(Ok("-Copt-level=s".into()), false).unwrap();
+        assert_eq!(rustflags_log(&flags), r#"+ RUSTFLAGS="-Copt-level=s""#);
+    }
+
+    #[test]
+    fn test_build_args_features() {
+        let args = BuildArgs::parse_args_default(&[
+            "--features",
+            "a,b",
+            "--features",
+            "c",
+            "--no-default-features",
+        ])
+        .unwrap();
+        assert_eq!(
+            build_args(&args),
+            ["--release", "--features=a,b,c", "--no-default-features"]
+        );
+
+        let args = BuildArgs::parse_args_default(&["--all-features", "--release"]).unwrap();
+        assert_eq!(build_args(&args), ["--release", "--all-features"]);
+    }
+
+    #[test]
+    fn test_build_args_profile() {
+        let args = BuildArgs::parse_args_default(&["--profile", "dev"]).unwrap();
+        assert_eq!(build_args(&args), ["--profile=dev"]);
+        assert!(!is_release(&args));
+
+        let args = BuildArgs::parse_args_default::<&str>(&[]).unwrap();
+        assert_eq!(build_args(&args), ["--release"]);
+        assert!(is_release(&args));
+    }
+
+    #[test]
+    fn test_filter_packages() {
+        let json = [
+            r#"{"reason":"compiler-artifact","package_id":"hello 0.1.0 (path+file:///t/hello)","target":{"name":"hello"},"executable":"/t/hello"}"#,
+            r#"{"reason":"compiler-artifact","package_id":"path+file:///t/world#world-rom@0.1.0","target":{"name":"world"},"executable":"/t/world"}"#,
+        ]
+        .join("\n");
+        let packages = |names: &[&str]| names.iter().map(|&n| n.to_owned()).collect::<Vec<_>>();
+
+        let artifacts = parse_artifacts(&json).unwrap();
+        let hello = filter_packages(artifacts, &packages(&["hello"])).unwrap();
+        assert_eq!(hello.len(), 1);
+        assert_eq!(hello[0].executable, "/t/hello");
+
+        let artifacts = parse_artifacts(&json).unwrap();
+        let world = filter_packages(artifacts, &packages(&["world-rom"])).unwrap();
+        assert_eq!(world[0].executable, "/t/world");
+
+        let artifacts = parse_artifacts(&json).unwrap();
+        let missing = filter_packages(artifacts, &packages(&["hello", "nope"]));
+        assert!(matches!(missing, Err(SubcommandError::PackageArtifact(p)) if p == ["nope"]));
+    }
+
+    #[test]
+    fn test_split_output() {
+        let json = [
+            r#"{"reason":"build-script-executed","package_id":"n64 0.1.0","linked_libs":[],"linked_paths":[]}"#,
+            r#"[n64 0.1.0] cargo:rerun-if-changed=build.rs"#,
+            r#"{"reason":"compiler-message","message":{"rendered":"warning: \"reason\":\"compiler-artifact\"\n"}}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"hello"},"executable":"/t/hello"}"#,
+            r#"{"reason":"compiler-message","message":{"rendered":"warning: ] cargo: in a string\n"}}"#,
+            "",
+            r#"{"reason":"build-finished","success":true}"#,
+        ]
+        .join("\n");
+
+        let (artifacts, messages) = split_output(&json);
+
+        assert_eq!(artifacts.len(), 1);
+        assert!(artifacts[0].contains("/t/hello"));
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|m| m.contains("warning:")));
+    }
+
+    #[test]
+    fn test_linked_libs() {
+        let json = [
+            r#"{"reason":"build-script-executed","package_id":"n64 0.1.0","linked_libs":["static=c","m"],"linked_paths":[]}"#,
+            r#"{"reason":"build-script-executed","package_id":"rrt0 0.1.0","linked_libs":[],"linked_paths":[]}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"hello"},"executable":"/t/hello"}"#,
+        ]
+        .join("\n");
+
+        assert_eq!(linked_libs(&json), ["static=c", "m"]);
+    }
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(package_name("hello 0.1.0 (path+file:///t/hello)"), "hello");
+        assert_eq!(package_name("path+file:///t/hello#hello@0.1.0"), "hello");
+        assert_eq!(package_name("path+file:///t/hello#0.1.0"), "hello");
+        assert_eq!(
+            package_name("git+https://github.com/rust-console/hello?branch=main#0.1.0"),
+            "hello"
+        );
+    }
+}
@@ -1,9 +1,12 @@
+use crate::elf::{Endian, BUILTIN_SECTIONS};
 use crate::ipl3::IPL3;
+use crate::save::SaveType;
 use gumdrop::Options;
 use std::env;
-use std::fs::{self, File};
-use std::io::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -20,6 +23,57 @@ pub enum ArgParseError {
     #[error("`--ipl3` and `--ipl3-from-rom` are mutually exclusive")]
     AmbiguousIPL3Value,
 
+    #[error("`--elf` is required")]
+    MissingElfValue,
+
+    #[error("`--fs` and `--fs-image` are mutually exclusive")]
+    AmbiguousFSValue,
+
+    #[error("Expected IPL3 {0}, found {1}")]
+    RequiredCICMismatch(String, String),
+
+    #[error("`--quiet` and `--verbose` are mutually exclusive")]
+    AmbiguousVerbosity,
+
+    #[error("`--message-format=json` cannot be combined with `--output -`")]
+    JsonStdoutConflict,
+
+    #[error("`--emit-hash` cannot be combined with `--output -`")]
+    HashStdoutConflict,
+
+    #[error("`--dry-run` cannot be used with `run`, no ROM is written to run")]
+    DryRunConflict,
+
+    #[error("`--profile` cannot be combined with `--release`")]
+    AmbiguousProfile,
+
+    #[error("Invalid feature `{0}`")]
+    InvalidFeature(String),
+
+    #[error("Section `{0}` is always dumped, it cannot be given with `--section`")]
+    BuiltinSection(String),
+
+    #[error("`--rom` is required")]
+    MissingRomValue,
+
+    #[error("A shell is required, one of bash, zsh, fish")]
+    MissingShell,
+
+    #[error("Unsupported shell `{0}`, expected one of bash, zsh, fish")]
+    UnsupportedShell(String),
+
+    #[error("Linker script `{0}` not found")]
+    LinkerScriptNotFound(String),
+
+    #[error("`--linker-script` cannot be combined with `--target`")]
+    AmbiguousLinkerScript,
+
+    #[error("Unable to read target `{0}`")]
+    TargetReadError(String),
+
+    #[error("Target `{0}` is for `{1}`, expected a MIPS target for Nintendo 64")]
+    NonMipsTarget(String, String),
+
     #[error("Error creating target or linker script: {0}")]
     TargetCreationError(String),
 
@@ -41,16 +95,79 @@ pub(crate) struct Args {
     #[options(short = "v", count)]
     pub(crate) verbose: usize,
 
+    /// Do not print progress messages, only warnings and errors
+    #[options(short = "q")]
+    pub(crate) quiet: bool,
+
+    /// Output format for build results, `human` or `json` (Default: human)
+    #[options(no_short, meta = "FMT")]
+    pub(crate) message_format: Option<MessageFormat>,
+
     /// Available subcommands
     #[options(command)]
     pub(crate) subcommand: Option<Subcommand>,
 }
 
+/// Output format for build results and errors.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MessageFormat {
+    #[default]
+    Human,
+
+    /// One JSON object per line on stdout
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!("Expected `human` or `json`, found `{}`", s)),
+        }
+    }
+}
+
+/// Whether JSON messages are requested by the global options in `args`. This is
+/// used to format errors, even when argument parsing fails.
+pub(crate) fn json_requested<T: AsRef<str>>(args: &[T]) -> bool {
+    let mut args = args.iter().skip(1).map(|arg| arg.as_ref());
+
+    while let Some(arg) = args.next() {
+        match arg {
+            "--message-format=json" => return true,
+            "--message-format" => return args.next() == Some("json"),
+            _ if !arg.starts_with('-') => break,
+            _ => (),
+        }
+    }
+
+    false
+}
+
 #[derive(Debug, Options)]
 pub(crate) enum Subcommand {
     /// Build an executable ROM for Nintendo 64
     #[options()]
     Build(BuildArgs),
+
+    /// Build an executable ROM and run it in an emulator
+    #[options()]
+    Run(BuildArgs),
+
+    /// Convert a prebuilt ELF to an executable ROM without running cargo
+    #[options(name = "elf2rom")]
+    Elf2Rom(BuildArgs),
+
+    /// Recompute the header CRCs of an existing ROM in place
+    #[options()]
+    FixCrc(FixCrcArgs),
+
+    /// Show the IPL3 and CRCs of an existing ROM
+    #[options()]
+    Inspect(InspectArgs),
 }
 
 #[derive(Debug, Options)]
@@ -59,13 +176,115 @@ pub(crate) struct BuildArgs {
     #[options()]
     pub(crate) target: Option<String>,
 
+    /// Linker script to use instead of the bundled one, cannot be combined with `--target`
+    #[options(meta = "PATH")]
+    pub(crate) linker_script: Option<String>,
+
+    /// Prebuilt ELF to convert instead of running cargo, required by `elf2rom`
+    #[options(meta = "PATH")]
+    pub(crate) elf: Option<String>,
+
+    /// Build all binaries in the workspace, producing one ROM per binary
+    #[options()]
+    pub(crate) workspace: bool,
+
+    /// Build with the given profile instead of `release`, link-time optimization
+    /// is only enabled for `release`
+    #[options(meta = "NAME")]
+    pub(crate) profile: Option<String>,
+
+    /// Do not add `-Clinker-plugin-lto` to RUSTFLAGS
+    #[options()]
+    pub(crate) no_lto: bool,
+
+    /// Package to build, may be repeated
+    #[options(short = "p", meta = "SPEC")]
+    pub(crate) package: Vec<String>,
+
+    /// Comma separated list of features to activate, may be repeated
+    #[options(meta = "FEATURES")]
+    pub(crate) features: Vec<String>,
+
+    /// Do not activate the `default` feature
+    #[options()]
+    pub(crate) no_default_features: bool,
+
+    /// Activate all available features
+    #[options()]
+    pub(crate) all_features: bool,
+
     /// Program name (Default: Crate name)
     #[options()]
     pub(crate) name: Option<String>,
 
-    /// Path to a directory for creating the embedded file system
+    /// Region code written to the header (Default: E)
+    #[options(meta = "CODE", parse(try_from_str = "parse_region"))]
+    pub(crate) region: Option<u8>,
+
+    /// Use the region code matching the IPL3 when `--region` is not given
+    #[options()]
+    pub(crate) region_from_cic: bool,
+
+    /// Derive the cartridge ID from the crate name instead of using `KW`
+    #[options()]
+    pub(crate) deterministic_cart_id_from_name: bool,
+
+    /// Clock rate written to the header (Default: 15)
+    #[options(meta = "RATE")]
+    pub(crate) clock_rate: Option<u32>,
+
+    /// Output ROM path, or `-` for stdout (Default: Next to the ELF)
+    #[options(meta = "PATH")]
+    pub(crate) output: Option<String>,
+
+    /// Path to a directory for creating the embedded file system, may be
+    /// repeated to merge several directories
+    #[options()]
+    pub(crate) fs: Vec<String>,
+
+    /// Path to a prebuilt FAT image to append as the embedded file system
+    #[options(meta = "PATH")]
+    pub(crate) fs_image: Option<String>,
+
+    /// Build a program-only ROM, ignoring any `--fs` or `--fs-image`
+    #[options()]
+    pub(crate) no_fs: bool,
+
+    /// Validate the ELF and compute the ROM image without writing any files,
+    /// use with `--elf` to also skip the cargo build
+    #[options()]
+    pub(crate) dry_run: bool,
+
+    /// Glob pattern for files to leave out of the embedded file system, may be repeated
+    #[options(meta = "PATTERN")]
+    pub(crate) fs_exclude: Vec<String>,
+
+    /// Include dotfiles in the embedded file system
     #[options()]
-    pub(crate) fs: Option<String>,
+    pub(crate) fs_dotfiles: bool,
+
+    /// Volume label for the embedded file system, up to 11 characters (Default: TECHNEKDISK)
+    #[options(meta = "LABEL", parse(try_from_str = "parse_fs_label"))]
+    pub(crate) fs_label: Option<[u8; 11]>,
+
+    /// Bytes reserved for FAT in the embedded file system, accepts `K` and `M` suffixes
+    /// (Default: scaled by the number of files)
+    #[options(meta = "SIZE", parse(try_from_str = "parse_size"))]
+    pub(crate) fs_reserved: Option<usize>,
+
+    /// Align the file system within the ROM to a power of 2, e.g. `512` or `4K`
+    #[options(meta = "SIZE", parse(try_from_str = "parse_alignment"))]
+    pub(crate) fs_align: Option<usize>,
+
+    /// Place a file at a fixed ROM offset after the file system, e.g.
+    /// `0x200000=overlay.bin`, may be repeated. Embedded files are outside of
+    /// the CRC window.
+    #[options(meta = "OFFSET=PATH", parse(try_from_str = "parse_embed"))]
+    pub(crate) embed: Vec<(usize, String)>,
+
+    /// Maximum directory depth for the embedded file system (Default: 64)
+    #[options(meta = "DEPTH")]
+    pub(crate) fs_max_depth: Option<usize>,
 
     /// Path to IPL3 (bootcode)
     #[options(meta = "PATH", parse(try_from_str = "IPL3::read"))]
@@ -75,11 +294,158 @@ pub(crate) struct BuildArgs {
     #[options(meta = "PATH", parse(try_from_str = "IPL3::read_from_rom"))]
     pub(crate) ipl3_from_rom: Option<IPL3>,
 
+    /// Only warn when the ELF machine or endianness is unexpected
+    #[options()]
+    pub(crate) skip_elf_validation: bool,
+
+    /// Extra ELF section to dump after the built-in ones, may be repeated
+    #[options(meta = "NAME")]
+    pub(crate) section: Vec<String>,
+
+    /// Byte order of the ELF, `big` or `little` (Default: big)
+    #[options(meta = "ENDIAN")]
+    pub(crate) elf_endian: Option<Endian>,
+
+    /// Write function symbols to a `.sym` file next to the ROM
+    #[options()]
+    pub(crate) emit_symbols: bool,
+
+    /// Fail unless the IPL3 is the given CIC, e.g. `6102`
+    #[options(meta = "CIC")]
+    pub(crate) ipl3_required_cic: Option<String>,
+
+    /// Append a zeroed save region after the ROM, one of `eeprom4k`, `eeprom16k`,
+    /// `sram`, `sram768k`, `flashram`
+    #[options(meta = "TYPE")]
+    pub(crate) append_empty_save: Option<SaveType>,
+
+    /// Print the header CRCs after building
+    #[options()]
+    pub(crate) show_crc: bool,
+
+    /// Write the offset and length of each ROM region to a `.json` file next to the ROM
+    #[options()]
+    pub(crate) emit_manifest: bool,
+
+    /// Write a SHA-256 of the ROM to `<rom>.sha256`, in `sha256sum` format
+    #[options()]
+    pub(crate) emit_hash: bool,
+
+    /// Emulator command for `run`, `{rom}` is replaced with the ROM path
+    /// (Default: $CARGO_N64_EMULATOR)
+    #[options(meta = "COMMAND")]
+    pub(crate) emulator: Option<String>,
+
+    /// Pad the ROM to exactly this size, accepts `K` and `M` suffixes
+    /// (Default: next power of 2 or multiple of 4M)
+    #[options(meta = "SIZE", parse(try_from_str = "parse_size"))]
+    pub(crate) pad_to: Option<usize>,
+
+    /// Fill byte for padding in hex, e.g. `0x00` (Default: 0xFF)
+    #[options(meta = "BYTE", parse(try_from_str = "parse_pad_byte"))]
+    pub(crate) pad_byte: Option<u8>,
+
+    /// Turn warnings about a likely broken ROM into errors
+    #[options()]
+    pub(crate) strict: bool,
+
+    /// Maximum program size in bytes, accepts `K` and `M` suffixes (Default: 1M)
+    #[options(meta = "SIZE", parse(try_from_str = "parse_size"))]
+    pub(crate) max_program_size: Option<usize>,
+
     /// All remaining arguments will be passed directly to cargo
     #[options(free)]
     pub(crate) rest: Vec<String>,
 }
 
+impl BuildArgs {
+    /// The IPL3 from either `--ipl3` or `--ipl3-from-rom`.
+    pub(crate) fn get_ipl3(&self) -> Option<&IPL3> {
+        self.ipl3.as_ref().or(self.ipl3_from_rom.as_ref())
+    }
+}
+
+#[derive(Debug, Options)]
+pub(crate) struct FixCrcArgs {
+    /// Path to the ROM to patch
+    #[options(meta = "PATH")]
+    pub(crate) rom: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub(crate) struct InspectArgs {
+    /// Path to the ROM to inspect
+    #[options(meta = "PATH")]
+    pub(crate) rom: Option<String>,
+
+    /// Report every known CIC whose CRCs match the ones stored in the header
+    #[options()]
+    pub(crate) crc_scan: bool,
+}
+
+/// Parse a size in bytes. `K` and `M` suffixes multiply by 1024 and 1024 * 1024.
+pub(crate) fn parse_size(size: &str) -> Result<usize, String> {
+    let (number, multiplier) = if let Some(number) = size.strip_suffix(&['K', 'k'][..]) {
+        (number, 1024)
+    } else if let Some(number) = size.strip_suffix(&['M', 'm'][..]) {
+        (number, 1024 * 1024)
+    } else {
+        (size, 1)
+    };
+
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size `{}`", size))
+}
+
+/// Parse a size in bytes that is a power of 2, see [`parse_size`].
+fn parse_alignment(alignment: &str) -> Result<usize, String> {
+    match parse_size(alignment)? {
+        size if size.is_power_of_two() => Ok(size),
+        _ => Err(format!("Alignment `{}` is not a power of 2", alignment)),
+    }
+}
+
+/// Parse an embedded file as `OFFSET=PATH`. The offset is in hex with a `0x`
+/// prefix, or a size, see [`parse_size`].
+fn parse_embed(embed: &str) -> Result<(usize, String), String> {
+    let (offset, path) = embed
+        .split_once('=')
+        .ok_or_else(|| format!("Expected `OFFSET=PATH`, found `{}`", embed))?;
+
+    let offset = match offset
+        .strip_prefix("0x")
+        .or_else(|| offset.strip_prefix("0X"))
+    {
+        Some(digits) => {
+            usize::from_str_radix(digits, 16).map_err(|_| format!("Invalid offset `{}`", offset))?
+        }
+        None => parse_size(offset)?,
+    };
+
+    Ok((offset, path.to_owned()))
+}
+
+/// Parse a byte in hex, with or without a `0x` prefix.
+fn parse_pad_byte(byte: &str) -> Result<u8, String> {
+    let digits = byte
+        .strip_prefix("0x")
+        .or_else(|| byte.strip_prefix("0X"))
+        .unwrap_or(byte);
+
+    u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex byte `{}`", byte))
+}
+
+/// Parse a single-character region code, e.g. `E` or `J`.
+fn parse_region(region: &str) -> Result<u8, String> {
+    match region.as_bytes() {
+        [code] if code.is_ascii_alphanumeric() => Ok(*code),
+        _ => Err(format!("Invalid region code `{}`", region)),
+    }
+}
+
 fn print_usage(args: Args) {
     println!("{}", env!("CARGO_PKG_NAME"));
     println!("Nintendo 64 build tool");
@@ -88,6 +454,10 @@ fn print_usage(args: Args) {
 
     let command = match args.subcommand {
         Some(Subcommand::Build(_)) => "build",
+        Some(Subcommand::Run(_)) => "run",
+        Some(Subcommand::Elf2Rom(_)) => "elf2rom",
+        Some(Subcommand::FixCrc(_)) => "fix-crc",
+        Some(Subcommand::Inspect(_)) => "inspect",
         None => "<COMMAND>",
     };
     println!("  cargo n64 {} [OPTIONS]", command);
@@ -126,60 +496,443 @@ pub(crate) fn parse_args<T: AsRef<str>>(args: &[T]) -> Result<Args, ArgParseErro
         process::exit(0);
     }
 
-    if let Some(Subcommand::Build(ref mut build_args)) = args.subcommand {
-        // IPL3 args are required and mutually exclusive
-        if build_args.ipl3.is_none() && build_args.ipl3_from_rom.is_none() {
-            return Err(MissingIPL3Value);
+    if args.quiet && args.verbose > 0 {
+        return Err(AmbiguousVerbosity);
+    }
+
+    if let Some(Subcommand::Run(ref build_args)) = args.subcommand {
+        if build_args.dry_run {
+            return Err(DryRunConflict);
         }
-        if build_args.ipl3.is_some() && build_args.ipl3_from_rom.is_some() {
-            return Err(AmbiguousIPL3Value);
+    }
+
+    if let Some(Subcommand::Elf2Rom(ref build_args)) = args.subcommand {
+        if build_args.elf.is_none() {
+            return Err(MissingElfValue);
         }
+    }
 
-        // Set default target
-        build_args.target.get_or_insert(create_target()?);
+    match args.subcommand {
+        Some(Subcommand::Build(ref mut build_args))
+        | Some(Subcommand::Run(ref mut build_args))
+        | Some(Subcommand::Elf2Rom(ref mut build_args)) => {
+            // `--release` passed through to cargo would override the profile
+            if build_args.profile.is_some() && build_args.rest.iter().any(|arg| arg == "--release")
+            {
+                return Err(AmbiguousProfile);
+            }
+            if let Some(name) = build_args
+                .section
+                .iter()
+                .find(|name| BUILTIN_SECTIONS.contains(&name.as_str()))
+            {
+                return Err(BuiltinSection(name.to_owned()));
+            }
+
+            // IPL3 args are required and mutually exclusive
+            if build_args.ipl3.is_none() && build_args.ipl3_from_rom.is_none() {
+                return Err(MissingIPL3Value);
+            }
+            if build_args.ipl3.is_some() && build_args.ipl3_from_rom.is_some() {
+                return Err(AmbiguousIPL3Value);
+            }
+            check_features(&build_args.features)?;
+            if !build_args.fs.is_empty() && build_args.fs_image.is_some() {
+                return Err(AmbiguousFSValue);
+            }
+            if args.message_format == Some(MessageFormat::Json)
+                && build_args.output.as_deref() == Some("-")
+            {
+                return Err(JsonStdoutConflict);
+            }
+            if build_args.emit_hash && build_args.output.as_deref() == Some("-") {
+                return Err(HashStdoutConflict);
+            }
+            if let Some(ref cic) = build_args.ipl3_required_cic {
+                check_required_cic(build_args.get_ipl3().unwrap(), cic)?;
+            }
+
+            // Set default target, nothing is built from a prebuilt ELF
+            if let Some(ref target) = build_args.target {
+                if build_args.linker_script.is_some() {
+                    return Err(AmbiguousLinkerScript);
+                }
+                check_target(target)?;
+            } else if build_args.elf.is_none() {
+                let linker_script = build_args
+                    .linker_script
+                    .as_deref()
+                    .map(find_linker_script)
+                    .transpose()?;
+                build_args.target = Some(create_target(linker_script.as_deref())?);
+            }
+        }
+        Some(Subcommand::FixCrc(ref fix_crc_args)) => {
+            if fix_crc_args.rom.is_none() {
+                return Err(MissingRomValue);
+            }
+        }
+        Some(Subcommand::Inspect(ref inspect_args)) => {
+            if inspect_args.rom.is_none() {
+                return Err(MissingRomValue);
+            }
+        }
+        None => (),
     }
 
     Ok(args)
 }
 
+/// Validate feature names before they are passed to cargo. Features are separated
+/// by commas or spaces, and may be prefixed with a package name, e.g. `pkg/feat`.
+/// Empty names are skipped like cargo does, e.g. in `--features "a, b"`.
+fn check_features(features: &[String]) -> Result<(), ArgParseError> {
+    let valid = |c: char| c.is_alphanumeric() || "-_+./".contains(c);
+    let valid_start = |c: char| c.is_alphanumeric() || c == '_';
+
+    let features = features
+        .iter()
+        .flat_map(|f| f.split(&[',', ' '][..]))
+        .map(str::trim)
+        .filter(|feature| !feature.is_empty());
+    for feature in features {
+        if !feature.starts_with(valid_start) || !feature.chars().all(valid) {
+            return Err(ArgParseError::InvalidFeature(feature.to_owned()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a FAT volume label, which is upper case ASCII padded with spaces.
+fn parse_fs_label(s: &str) -> Result<[u8; 11], String> {
+    if s.len() > 11 || !s.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        return Err(format!(
+            "Expected up to 11 printable ASCII characters, found `{}`",
+            s
+        ));
+    }
+
+    let mut label = [b' '; 11];
+    label[..s.len()].copy_from_slice(s.to_ascii_uppercase().as_bytes());
+
+    Ok(label)
+}
+
+/// Fail fast when `--target` is a target json for another architecture, instead
+/// of at ELF validation after the whole build. Bare target names are left to
+/// cargo, since they may name a json found through `RUST_TARGET_PATH`.
+fn check_target(target: &str) -> Result<(), ArgParseError> {
+    use self::ArgParseError::*;
+
+    if !target.ends_with(".json") {
+        return Ok(());
+    }
+
+    let json = fs::read_to_string(target).map_err(|_| TargetReadError(target.to_owned()))?;
+    let spec: serde_json::Value =
+        serde_json::from_str(&json).map_err(|_| TargetReadError(target.to_owned()))?;
+
+    let arch = spec["arch"]
+        .as_str()
+        .or_else(|| spec["llvm-target"].as_str())
+        .unwrap_or_default();
+
+    if arch.starts_with("mips") {
+        Ok(())
+    } else {
+        Err(NonMipsTarget(target.to_owned(), arch.to_owned()))
+    }
+}
+
+/// Assert that the resolved IPL3 is the required CIC.
+fn check_required_cic(ipl3: &IPL3, cic: &str) -> Result<(), ArgParseError> {
+    if ipl3.is_cic(cic) {
+        Ok(())
+    } else {
+        Err(ArgParseError::RequiredCICMismatch(
+            cic.to_owned(),
+            ipl3.to_string(),
+        ))
+    }
+}
+
+/// Resolve a user-provided linker script to an absolute path, so it is found
+/// regardless of the directory cargo invokes the linker from.
+fn find_linker_script(path: &str) -> Result<PathBuf, ArgParseError> {
+    fs::canonicalize(path).map_err(|_| ArgParseError::LinkerScriptNotFound(path.to_owned()))
+}
+
 /// Create a target triple JSON file and linker script in a temporary directory.
 /// This is necessary because we don't want users to have to specify the
 /// `--target` option on every build, and we have practically no chance to get
 /// it into the compiler as a default target. Just being realistic. :P
 ///
 /// Both files are compiled into the executable, the JSON is a template because
-/// it needs a path reference to the linker script.
-fn create_target() -> Result<String, ArgParseError> {
-    // Sad, but this little helper function really simplifies the error handling
-    fn path_to_string(path: &std::path::Path) -> String {
-        path.to_string_lossy().to_string().replace('\\', "/")
-    }
-
+/// it needs a path reference to the linker script. The bundled linker script is
+/// not written when `linker_script` is given, the JSON refers to it instead.
+///
+/// The files go in a sub-directory named after a hash of their contents, so
+/// concurrent builds with different versions or linker scripts never share
+/// files. Files that are already up to date are left alone.
+fn create_target(linker_script: Option<&Path>) -> Result<String, ArgParseError> {
     use self::ArgParseError::*;
 
+    const LINKER_SCRIPT: &[u8] = include_bytes!("templates/linker.ld");
+
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(include_bytes!("templates/mips-nintendo64-none.fmt"));
+    match linker_script {
+        Some(linker_script) => hasher.update(path_to_string(linker_script).as_bytes()),
+        None => hasher.update(LINKER_SCRIPT),
+    }
+
     let mut path = env::temp_dir();
     path.push("n64-build");
+    path.push(format!("{:08x}", hasher.finalize()));
 
     // Create our temporary sub-directory for storing the target files
     fs::create_dir_all(&path).map_err(|_| TargetCreationError(path_to_string(&path)))?;
 
     // Create the linker script first
-    let mut linker_script = path.clone();
-    linker_script.push("linker.ld");
-    let mut file = File::create(&linker_script)
-        .map_err(|_| TargetCreationError(path_to_string(&linker_script)))?;
-    file.write_all(include_bytes!("templates/linker.ld"))
-        .map_err(|_| TargetWriteError(path_to_string(&linker_script)))?;
+    let linker_script = match linker_script {
+        Some(linker_script) => linker_script.to_path_buf(),
+        None => {
+            let linker_script = path.join("linker.ld");
+            write_if_changed(&linker_script, LINKER_SCRIPT)?;
+
+            linker_script
+        }
+    };
 
     // Create the target spec next
     path.push("mips-nintendo64-none.json");
-    let mut file = File::create(&path).map_err(|_| TargetCreationError(path_to_string(&path)))?;
     let data = format!(
         include_str!("templates/mips-nintendo64-none.fmt"),
         path_to_string(&linker_script)
     );
-    file.write_all(data.as_bytes())
-        .map_err(|_| TargetWriteError(path_to_string(&path)))?;
+    write_if_changed(&path, data.as_bytes())?;
 
     Ok(path_to_string(&path))
 }
+
+/// Sad, but this little helper function really simplifies the error handling
+fn path_to_string(path: &Path) -> String {
+    path.to_string_lossy().to_string().replace('\\', "/")
+}
+
+/// Write `contents` to `path` unless it already holds them. The file is written
+/// under a temporary name and renamed, so a concurrent build never reads a
+/// partially written file.
+fn write_if_changed(path: &Path, contents: &[u8]) -> Result<(), ArgParseError> {
+    use self::ArgParseError::*;
+
+    if fs::read(path).map_or(false, |existing| existing == contents) {
+        return Ok(());
+    }
+
+    let temp = path.with_extension(format!("{}.tmp", process::id()));
+    fs::write(&temp, contents).map_err(|_| TargetWriteError(path_to_string(&temp)))?;
+    fs::rename(&temp, path).map_err(|_| TargetWriteError(path_to_string(path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_features, check_required_cic, check_target, create_target, find_linker_script,
+        json_requested, parse_alignment, parse_args, parse_embed, parse_fs_label, parse_pad_byte,
+        parse_region, parse_size, ArgParseError,
+    };
+    use crate::ipl3::{IPL3, IPL_SIZE};
+    use crate::test_util::scratch_dir;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("2M"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("2m"), Ok(2 * 1024 * 1024));
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("1G").is_err());
+    }
+
+    #[test]
+    fn test_create_target_cached() {
+        let target = create_target(None).unwrap();
+        let modified = std::fs::metadata(&target).unwrap().modified().unwrap();
+
+        assert!(target.ends_with("/mips-nintendo64-none.json"));
+        assert_eq!(create_target(None).unwrap(), target);
+        assert_eq!(
+            std::fs::metadata(&target).unwrap().modified().unwrap(),
+            modified
+        );
+
+        let custom = create_target(Some(std::path::Path::new("/custom/linker.ld"))).unwrap();
+        assert_ne!(custom, target);
+        assert!(std::fs::read_to_string(&custom)
+            .unwrap()
+            .contains("--script=/custom/linker.ld"));
+    }
+
+    #[test]
+    fn test_find_linker_script() {
+        let found = find_linker_script("src/templates/linker.ld").unwrap();
+
+        assert!(found.is_absolute());
+        assert!(found.ends_with("src/templates/linker.ld"));
+        assert!(find_linker_script("src/templates/missing.ld").is_err());
+    }
+
+    #[test]
+    fn test_parse_alignment() {
+        assert_eq!(parse_alignment("512"), Ok(512));
+        assert_eq!(parse_alignment("4K"), Ok(4096));
+        assert!(parse_alignment("0").is_err());
+        assert!(parse_alignment("3K").is_err());
+    }
+
+    #[test]
+    fn test_parse_embed() {
+        assert_eq!(
+            parse_embed("0x200000=a.bin"),
+            Ok((0x20_0000, "a.bin".into()))
+        );
+        assert_eq!(parse_embed("2M=a=b.bin"), Ok((0x20_0000, "a=b.bin".into())));
+        assert!(parse_embed("a.bin").is_err());
+        assert!(parse_embed("0xzz=a.bin").is_err());
+    }
+
+    #[test]
+    fn test_parse_pad_byte() {
+        assert_eq!(parse_pad_byte("0xff"), Ok(0xff));
+        assert_eq!(parse_pad_byte("0X00"), Ok(0x00));
+        assert_eq!(parse_pad_byte("a5"), Ok(0xa5));
+        assert!(parse_pad_byte("0x100").is_err());
+        assert!(parse_pad_byte("0x").is_err());
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(parse_region("J"), Ok(b'J'));
+        assert!(parse_region("").is_err());
+        assert!(parse_region("EU").is_err());
+    }
+
+    #[test]
+    fn test_parse_fs_label() {
+        assert_eq!(parse_fs_label("game"), Ok(*b"GAME       "));
+        assert_eq!(parse_fs_label("TECHNEKDISK"), Ok(*b"TECHNEKDISK"));
+        assert!(parse_fs_label("TOOLONGLABEL").is_err());
+        assert!(parse_fs_label("dísk").is_err());
+    }
+
+    #[test]
+    fn test_json_requested() {
+        assert!(json_requested(&["n64", "--message-format=json", "build"]));
+        assert!(json_requested(&[
+            "n64",
+            "-v",
+            "--message-format",
+            "json",
+            "build"
+        ]));
+        assert!(!json_requested(&["n64", "--message-format=human", "build"]));
+        assert!(!json_requested(&["n64", "build", "--message-format=json"]));
+    }
+
+    #[test]
+    fn test_check_features() {
+        assert!(check_features(&["audio".into(), "gfx,pkg/debug".into()]).is_ok());
+        assert!(check_features(&["audio,".into(), "a, b".into(), " gfx ".into()]).is_ok());
+        assert!(check_features(&["--all-features".into()]).is_err());
+        assert!(check_features(&["a;b".into()]).is_err());
+    }
+
+    #[test]
+    fn test_elf2rom_requires_elf() {
+        assert!(matches!(
+            parse_args(&["n64", "elf2rom"]),
+            Err(ArgParseError::MissingElfValue)
+        ));
+        assert!(matches!(
+            parse_args(&["n64", "elf2rom", "--elf", "hello.elf"]),
+            Err(ArgParseError::MissingIPL3Value)
+        ));
+    }
+
+    #[test]
+    fn test_builtin_section() {
+        assert!(matches!(
+            parse_args(&["n64", "build", "--section", ".text"]),
+            Err(ArgParseError::BuiltinSection(name)) if name == ".text"
+        ));
+        assert!(matches!(
+            parse_args(&["n64", "build", "--section", ".ipl3font"]),
+            Err(ArgParseError::MissingIPL3Value)
+        ));
+    }
+
+    #[test]
+    fn test_profile_release_conflict() {
+        assert!(matches!(
+            parse_args(&["n64", "build", "--profile", "dev", "--", "--release"]),
+            Err(ArgParseError::AmbiguousProfile)
+        ));
+        assert!(matches!(
+            parse_args(&["n64", "build", "--profile", "dev"]),
+            Err(ArgParseError::MissingIPL3Value)
+        ));
+    }
+
+    #[test]
+    fn test_quiet_verbose_conflict() {
+        assert!(matches!(
+            parse_args(&["n64", "-q", "-v"]),
+            Err(ArgParseError::AmbiguousVerbosity)
+        ));
+        assert!(parse_args(&["n64", "--quiet"]).unwrap().quiet);
+    }
+
+    #[test]
+    fn test_check_target() {
+        assert!(check_target("mips-nintendo64-none").is_ok());
+        assert!(check_target("n64").is_ok());
+
+        let dir = scratch_dir("check-target");
+        let write = |name: &str, json: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, json).unwrap();
+            path.to_string_lossy().to_string()
+        };
+
+        let mips = write(
+            "mips.json",
+            r#"{"arch": "mips", "llvm-target": "mips-unknown-unknown"}"#,
+        );
+        let arm = write(
+            "arm.json",
+            r#"{"arch": "arm", "llvm-target": "armv7a-none-eabi"}"#,
+        );
+        let llvm = write("llvm.json", r#"{"llvm-target": "mips-unknown-unknown"}"#);
+        assert!(check_target(&mips).is_ok());
+        assert!(matches!(
+            check_target(&arm),
+            Err(ArgParseError::NonMipsTarget(_, arch)) if arch == "arm"
+        ));
+        assert!(check_target(&llvm).is_ok());
+        assert!(matches!(
+            check_target(&dir.join("missing.json").to_string_lossy()),
+            Err(ArgParseError::TargetReadError(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_required_cic() {
+        assert!(check_required_cic(&IPL3::Cic6102([0; IPL_SIZE]), "6102").is_ok());
+        assert!(check_required_cic(&IPL3::Cic6105([0; IPL_SIZE]), "6102").is_err());
+        assert!(check_required_cic(&IPL3::Unknown([0; IPL_SIZE]), "6102").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::cli::{ArgParseError, Args};
+use gumdrop::Options;
+
+/// Name of the hidden subcommand that prints completion scripts
+pub(crate) const COMMAND: &str = "completions";
+
+/// Global options that take a separate value, e.g. `--message-format json`.
+const VALUE_OPTIONS: &[&str] = &["--message-format"];
+
+/// Return the shell name when `args` request completions, e.g. `n64 completions bash`.
+/// Global options may come before the subcommand, as with the other subcommands,
+/// e.g. `n64 -v completions bash`.
+pub(crate) fn requested<T: AsRef<str>>(args: &[T]) -> Option<Option<&str>> {
+    let (n64, args) = args.split_first()?;
+    if n64.as_ref() != "n64" {
+        return None;
+    }
+
+    let mut args = args.iter().map(|arg| arg.as_ref());
+    while let Some(arg) = args.next() {
+        if VALUE_OPTIONS.contains(&arg) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            return (arg == COMMAND).then(|| args.next());
+        }
+    }
+
+    None
+}
+
+/// Generate a completion script for `shell`, one of `bash`, `zsh` or `fish`.
+/// Subcommands and options are taken from the gumdrop usage text, so the
+/// scripts never fall out of date.
+pub(crate) fn generate(shell: Option<&str>) -> Result<String, ArgParseError> {
+    let commands = commands();
+    let global = long_options(Args::usage());
+
+    match shell {
+        Some("bash") => Ok(bash(&commands, &global)),
+        Some("zsh") => Ok(zsh(&commands, &global)),
+        Some("fish") => Ok(fish(&commands, &global)),
+        Some(shell) => Err(ArgParseError::UnsupportedShell(shell.to_owned())),
+        None => Err(ArgParseError::MissingShell),
+    }
+}
+
+/// Subcommand names, each with its long options.
+fn commands() -> Vec<(&'static str, Vec<&'static str>)> {
+    Args::command_list()
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|command| {
+            let usage = Args::command_usage(command).unwrap_or_default();
+            (command, long_options(usage))
+        })
+        .collect()
+}
+
+/// Long options in gumdrop usage text, e.g. `--ipl3-from-rom`.
+fn long_options(usage: &'static str) -> Vec<&'static str> {
+    usage
+        .lines()
+        .filter(|line| line.trim_start().starts_with('-'))
+        .flat_map(|line| line.split_whitespace())
+        .map(|word| word.trim_end_matches(','))
+        .filter(|word| word.starts_with("--"))
+        .collect()
+}
+
+fn bash(commands: &[(&str, Vec<&str>)], global: &[&str]) -> String {
+    let names: Vec<_> = commands.iter().map(|(name, _)| *name).collect();
+    let cases: String = commands
+        .iter()
+        .map(|(name, options)| format!("        {}) opts=\"{}\" ;;\n", name, options.join(" ")))
+        .collect();
+
+    format!(
+        r#"_cargo_n64() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local command="" opts word
+    for word in "${{COMP_WORDS[@]:1:COMP_CWORD-1}}"; do
+        case "$word" in
+            {pattern}) command="$word" ;;
+        esac
+    done
+
+    case "$command" in
+{cases}        *) opts="{commands} {global}" ;;
+    esac
+    COMPREPLY=($(compgen -W "$opts" -- "$cur"))
+}}
+
+complete -F _cargo_n64 cargo-n64
+"#,
+        pattern = names.join("|"),
+        cases = cases,
+        commands = names.join(" "),
+        global = global.join(" "),
+    )
+}
+
+fn zsh(commands: &[(&str, Vec<&str>)], global: &[&str]) -> String {
+    let names: Vec<_> = commands.iter().map(|(name, _)| *name).collect();
+    let cases: String = commands
+        .iter()
+        .map(|(name, options)| format!("        {}) compadd -- {} ;;\n", name, options.join(" ")))
+        .collect();
+
+    format!(
+        r#"#compdef cargo-n64
+
+_cargo_n64() {{
+    local command=${{words[(r)({pattern})]}}
+
+    case $command in
+{cases}        *) compadd -- {commands} {global} ;;
+    esac
+}}
+
+_cargo_n64 "$@"
+"#,
+        pattern = names.join("|"),
+        cases = cases,
+        commands = names.join(" "),
+        global = global.join(" "),
+    )
+}
+
+fn fish(commands: &[(&str, Vec<&str>)], global: &[&str]) -> String {
+    let names: Vec<_> = commands.iter().map(|(name, _)| *name).collect();
+    let no_command = format!("not __fish_seen_subcommand_from {}", names.join(" "));
+
+    let mut script = format!(
+        "complete -c cargo-n64 -f -n '{}' -a '{}'\n",
+        no_command,
+        names.join(" ")
+    );
+    for option in global {
+        script.push_str(&format!(
+            "complete -c cargo-n64 -n '{}' -l {}\n",
+            no_command,
+            &option[2..]
+        ));
+    }
+    for (name, options) in commands {
+        for option in options {
+            script.push_str(&format!(
+                "complete -c cargo-n64 -n '__fish_seen_subcommand_from {}' -l {}\n",
+                name,
+                &option[2..]
+            ));
+        }
+    }
+
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, long_options, requested};
+
+    #[test]
+    fn test_requested() {
+        assert_eq!(requested(&["n64", "completions", "zsh"]), Some(Some("zsh")));
+        assert_eq!(requested(&["n64", "completions"]), Some(None));
+        assert_eq!(requested(&["n64", "build"]), None);
+        assert_eq!(
+            requested(&["n64", "-v", "completions", "bash"]),
+            Some(Some("bash"))
+        );
+        assert_eq!(
+            requested(&["n64", "--message-format", "json", "completions", "fish"]),
+            Some(Some("fish"))
+        );
+        assert_eq!(requested(&["n64", "build", "completions"]), None);
+    }
+
+    #[test]
+    fn test_long_options() {
+        let usage = "Optional arguments:\n  -h, --help       Print help\n      --ipl3-from-rom PATH\n                   Path to ROM";
+
+        assert_eq!(long_options(usage), ["--help", "--ipl3-from-rom"]);
+    }
+
+    #[test]
+    fn test_generate() {
+        for shell in ["bash", "zsh", "fish"] {
+            let script = generate(Some(shell)).unwrap();
+
+            assert!(script.contains("build"), "{}", shell);
+            assert!(script.contains("fix-crc"), "{}", shell);
+            assert!(script.contains("ipl3-from-rom"), "{}", shell);
+        }
+        assert!(generate(Some("powershell")).is_err());
+        assert!(generate(None).is_err());
+    }
+}
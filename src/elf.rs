@@ -1,8 +1,12 @@
+use colored::Colorize;
 use goblin::elf::section_header::SectionHeader;
 use goblin::elf::Elf;
 use goblin::error::Error as GoblinError;
+use std::cmp;
 use std::fs;
 use std::io;
+use std::ops::Range;
+use std::str::FromStr;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -17,26 +21,94 @@ pub enum ElfError {
     Dump(String),
 }
 
+/// Byte order of the input ELF.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Endian {
+    #[default]
+    Big,
+
+    /// Every word of the dumped program is byte-swapped to big-endian
+    Little,
+}
+
+impl FromStr for Endian {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "big" => Ok(Endian::Big),
+            "little" => Ok(Endian::Little),
+            _ => Err(format!("Expected `big` or `little`, found `{}`", s)),
+        }
+    }
+}
+
+/// Options controlling how an ELF is validated and dumped.
+#[derive(Debug, Default)]
+pub(crate) struct DumpOptions {
+    /// Expected byte order of the ELF
+    pub(crate) endian: Endian,
+
+    /// Downgrade machine and endianness validation failures to warnings
+    pub(crate) skip_validation: bool,
+
+    /// Collect function symbols within the dumped program
+    pub(crate) symbols: bool,
+
+    /// Extra sections to dump, in order, after the built-in ones
+    pub(crate) sections: Vec<String>,
+
+    /// Warn about suspicious layouts, such as large gaps between sections. At 2
+    /// and above, also print each dumped section and zero-filled gap.
+    pub(crate) verbose: usize,
+}
+
+/// Sections copied into the program, in order. Custom sections follow them.
+pub(crate) const BUILTIN_SECTIONS: [&str; 5] = [".boot", ".text", ".rodata", ".data", ".got"];
+
+/// Gaps between sections larger than this are most likely linker script mistakes.
+const MAX_GAP: u64 = 1024 * 1024;
+
+/// A flat binary dumped from an ELF, ready to be placed in a ROM.
+pub(crate) struct ElfDump {
+    pub(crate) entry_point: u32,
+    pub(crate) program: Vec<u8>,
+    pub(crate) symbols: Vec<Symbol>,
+
+    /// Address range of `.sbss` and `.bss`, which the runtime must clear
+    pub(crate) bss: Option<Range<u32>>,
+}
+
+/// A function symbol and its virtual address.
+pub(crate) struct Symbol {
+    pub(crate) address: u32,
+    pub(crate) name: String,
+}
+
 pub(crate) struct SectionInfo<'a> {
     header: &'a SectionHeader,
     binary: &'a [u8],
 }
 
-pub(crate) fn dump(filename: &str) -> Result<(u32, Vec<u8>), ElfError> {
-    use self::ElfError::Dump;
-    use goblin::elf::section_header;
-
+pub(crate) fn dump(filename: &str, options: &DumpOptions) -> Result<ElfDump, ElfError> {
     // Read the file
     let data = fs::read(filename)?;
 
+    dump_bytes(&data, options)
+}
+
+pub(crate) fn dump_bytes(data: &[u8], options: &DumpOptions) -> Result<ElfDump, ElfError> {
+    use self::ElfError::Dump;
+    use goblin::elf::section_header;
+
     // Parse it
-    let elf = Elf::parse(&data)?;
+    let elf = Elf::parse(data)?;
 
     // Do some basic validation
-    validate(&elf)?;
+    validate(&elf, options)?;
 
     // Dump .boot section
-    let section = dump_section(&elf, &data, ".boot")?;
+    let section = dump_section(&elf, data, ".boot")?;
 
     // Validate the .boot section
     if (section.header.sh_flags & u64::from(section_header::SHF_EXECINSTR)) == 0 {
@@ -50,36 +122,152 @@ pub(crate) fn dump(filename: &str) -> Result<(u32, Vec<u8>), ElfError> {
             "First byte of .boot section must be program entry point".into(),
         ));
     }
+    if section.header.sh_addr % 4 != 0 || section.header.sh_size % 4 != 0 {
+        return Err(Dump(format!(
+            ".boot section at {:#x} with size {:#x} must be word-aligned",
+            section.header.sh_addr, section.header.sh_size
+        )));
+    }
 
+    print_section(options, ".boot", section.header);
     let mut binary = section.binary.to_vec();
     let mut offset = section.header.sh_addr + section.header.sh_size;
+    let mut previous = ".boot";
 
     // Copy data sections
-    for name in [".text", ".rodata", ".data", ".got"].iter() {
-        let section = dump_section(&elf, &data, name);
-        if section.is_err() {
-            continue;
+    let builtin = BUILTIN_SECTIONS[1..].iter().copied();
+    let custom = options.sections.iter().map(String::as_str);
+    for name in builtin.chain(custom) {
+        let section = match dump_section(&elf, data, name) {
+            Ok(section) => section,
+            Err(e) => {
+                // Custom sections are expected to exist
+                if options.sections.iter().any(|s| s == name) {
+                    eprintln!("{} {}", "warning:".yellow().bold(), e);
+                }
+                continue;
+            }
+        };
+
+        // Sections must not overlap the ones already copied
+        let section_offset = section.header.sh_addr;
+        if section_offset < offset {
+            return Err(Dump(format!(
+                "{} section at {:#x} overlaps {} section ending at {:#x}",
+                name, section_offset, previous, offset
+            )));
         }
-        let section = section.unwrap();
 
         // Align the buffer to this section
-        let section_offset = section.header.sh_addr;
         if offset < section_offset {
-            let length = binary.len() + (section_offset - offset) as usize;
+            let gap = section_offset - offset;
+            if let Some(message) = check_gap(gap, previous, name).filter(|_| options.verbose > 0) {
+                eprintln!("{} {}", "warning:".yellow().bold(), message);
+            }
+            if options.verbose > 1 {
+                eprintln!(
+                    "{:>12} {}",
+                    "Zero-fill".green().bold(),
+                    format_zero_fill(gap, offset)
+                );
+            }
+
+            let length = binary.len() + gap as usize;
             binary.resize(length, 0);
             offset = section_offset;
         }
+        previous = name;
 
         // Append this section to the buffer
+        print_section(options, name, section.header);
         binary.extend_from_slice(section.binary);
 
         offset += section.header.sh_size;
     }
 
-    Ok((elf.header.e_entry as u32, binary))
+    // The ROM is big-endian, swap each word of a little-endian program
+    if elf.little_endian && options.endian == Endian::Little {
+        swap_words(&mut binary);
+    }
+
+    let bss = find_bss(&elf, offset)?;
+
+    let entry_point = elf.header.e_entry as u32;
+    let symbols = if options.symbols {
+        function_symbols(&elf, entry_point, binary.len())
+    } else {
+        Vec::new()
+    };
+
+    Ok(ElfDump {
+        entry_point,
+        program: binary,
+        symbols,
+        bss,
+    })
+}
+
+/// Warn about a large gap between two sections, which is zero-filled in the
+/// program.
+fn check_gap(gap: u64, previous: &str, name: &str) -> Option<String> {
+    if gap <= MAX_GAP {
+        return None;
+    }
+
+    Some(format!(
+        "Zero-filling {:#x} bytes between {} and {} sections",
+        gap, previous, name
+    ))
+}
+
+/// Print a dumped section at verbosity 2 and above.
+fn print_section(options: &DumpOptions, name: &str, header: &SectionHeader) {
+    if options.verbose > 1 {
+        eprintln!(
+            "{:>12} {}",
+            "Section".green().bold(),
+            format_section(name, header)
+        );
+    }
 }
 
-fn validate(elf: &Elf<'_>) -> Result<(), ElfError> {
+/// Describe a dumped section, e.g. `.text at 0x80001000, 0x2000 bytes`.
+fn format_section(name: &str, header: &SectionHeader) -> String {
+    format!(
+        "{} at {:#010x}, {:#x} bytes",
+        name, header.sh_addr, header.sh_size
+    )
+}
+
+/// Describe the zero-fill before a section, e.g. `0x10 bytes at 0x80000410`.
+fn format_zero_fill(gap: u64, offset: u64) -> String {
+    format!("{:#x} bytes at {:#010x}", gap, offset)
+}
+
+/// Collect function symbols that fall within the loaded program, sorted by address.
+fn function_symbols(elf: &Elf<'_>, start: u32, length: usize) -> Vec<Symbol> {
+    use goblin::elf::sym;
+
+    let range = u64::from(start)..u64::from(start) + length as u64;
+    let mut symbols: Vec<_> = elf
+        .syms
+        .iter()
+        .filter(|s| s.st_type() == sym::STT_FUNC && range.contains(&s.st_value))
+        .filter_map(|s| {
+            let name = elf.strtab.get_at(s.st_name)?;
+
+            Some(Symbol {
+                address: s.st_value as u32,
+                name: name.to_owned(),
+            })
+        })
+        .collect();
+    symbols.sort_by_key(|s| s.address);
+
+    symbols
+}
+
+fn validate(elf: &Elf<'_>, options: &DumpOptions) -> Result<(), ElfError> {
     use self::ElfError::Dump;
     use goblin::elf::header;
 
@@ -89,17 +277,15 @@ fn validate(elf: &Elf<'_>) -> Result<(), ElfError> {
     }
     if elf.header.e_machine != header::EM_MIPS {
         let e = format!("Unexpected ELF machine: {}", elf.header.e_machine);
-        return Err(Dump(e));
+        relaxed(options, e)?;
     }
     if elf.header.e_entry > u64::from(u32::max_value()) {
         let e = format!("Entry point out if range: {}", elf.header.e_entry);
         return Err(Dump(e));
     }
-    if elf.little_endian {
-        return Err(Dump(format!(
-            "Unexpected ELF endianness: {}",
-            elf.little_endian
-        )));
+    if elf.little_endian != (options.endian == Endian::Little) {
+        let e = format!("Unexpected ELF endianness: {}", elf.little_endian);
+        relaxed(options, e)?;
     }
     if elf.section_headers.is_empty() {
         return Err(Dump("Missing ELF section headers".into()));
@@ -108,6 +294,24 @@ fn validate(elf: &Elf<'_>) -> Result<(), ElfError> {
     Ok(())
 }
 
+/// Byte-swap each 32-bit word, zero-padding a trailing partial word.
+fn swap_words(binary: &mut Vec<u8>) {
+    binary.resize((binary.len() + 3) & !3, 0);
+    for word in binary.chunks_exact_mut(4) {
+        word.reverse();
+    }
+}
+
+/// Fails validation with `e`, or only warns when validation is skipped.
+fn relaxed(options: &DumpOptions, e: String) -> Result<(), ElfError> {
+    if options.skip_validation {
+        eprintln!("{} {}", "warning:".yellow().bold(), e);
+        Ok(())
+    } else {
+        Err(ElfError::Dump(e))
+    }
+}
+
 fn dump_section<'a>(
     elf: &'a Elf<'_>,
     data: &'a [u8],
@@ -116,11 +320,8 @@ fn dump_section<'a>(
     use self::ElfError::Dump;
 
     // Find the section by name
-    let header = elf
-        .section_headers
-        .iter()
-        .find(|&h| elf.shdr_strtab.get_at(h.sh_name).unwrap_or("") == name)
-        .ok_or_else(|| Dump(format!("Could not find {} section", name)))?;
+    let header =
+        find_section(elf, name).ok_or_else(|| Dump(format!("Could not find {} section", name)))?;
 
     // Get section data
     let start = header.sh_offset as usize;
@@ -131,3 +332,487 @@ fn dump_section<'a>(
 
     Ok(SectionInfo { header, binary })
 }
+
+fn find_section<'a>(elf: &'a Elf<'_>, name: &str) -> Option<&'a SectionHeader> {
+    elf.section_headers
+        .iter()
+        .find(|&h| elf.shdr_strtab.get_at(h.sh_name).unwrap_or("") == name)
+}
+
+/// Find the extent of the zero-initialized sections, which are not copied into
+/// the program. The first of them must directly follow the loaded sections
+/// ending at `end`, only separated by its alignment. The extent spans from the
+/// lowest start to the highest end, so a gap between them is cleared as well.
+fn find_bss(elf: &Elf<'_>, end: u64) -> Result<Option<Range<u32>>, ElfError> {
+    use self::ElfError::Dump;
+
+    let mut sections: Vec<_> = [".sbss", ".bss"]
+        .into_iter()
+        .filter_map(|name| Some((name, find_section(elf, name)?)))
+        .filter(|(_, header)| header.sh_size > 0)
+        .collect();
+    sections.sort_by_key(|(_, header)| header.sh_addr);
+
+    let (first, header) = match sections.first() {
+        Some(&section) => section,
+        None => return Ok(None),
+    };
+    let align = cmp::max(header.sh_addralign, 1);
+    let aligned_end = (end + align - 1) / align * align;
+    if header.sh_addr < end || header.sh_addr > aligned_end {
+        return Err(Dump(format!(
+            "{} section at {:#x} does not directly follow the loaded sections ending at {:#x}",
+            first, header.sh_addr, end
+        )));
+    }
+
+    let start = header.sh_addr;
+    let mut bss_end = start;
+    let mut previous = first;
+    for (name, header) in sections {
+        if header.sh_addr > bss_end {
+            eprintln!(
+                "{} {:#x} bytes between {} and {} sections are cleared along with them",
+                "warning:".yellow().bold(),
+                header.sh_addr - bss_end,
+                previous,
+                name
+            );
+        }
+
+        bss_end = cmp::max(bss_end, header.sh_addr + header.sh_size);
+        previous = name;
+    }
+
+    Ok(Some(start as u32..bss_end as u32))
+}
+
+/// Builders for minimal ELF images used as test fixtures.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use goblin::elf::section_header::{SHT_NOBITS, SHT_PROGBITS, SHT_STRTAB};
+
+    pub(crate) struct Section<'a> {
+        pub(crate) name: &'a str,
+        pub(crate) addr: u32,
+        pub(crate) flags: u32,
+        pub(crate) data: &'a [u8],
+
+        /// Zero-initialized, only the length of `data` is used
+        pub(crate) nobits: bool,
+    }
+
+    /// Build an ELF32 executable containing `sections`, in order.
+    pub(crate) fn build_elf(
+        machine: u16,
+        little_endian: bool,
+        entry: u32,
+        sections: &[Section<'_>],
+    ) -> Vec<u8> {
+        const EHDR_SIZE: usize = 52;
+        const SHDR_SIZE: usize = 40;
+
+        let half = |value: u16| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+        let word = |value: u32| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+
+        // Section names, the first entry is the empty name
+        let mut strtab = vec![0];
+        let mut names = Vec::new();
+        for name in sections.iter().map(|s| s.name).chain([".shstrtab"]) {
+            names.push(strtab.len() as u32);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+
+        // Section contents follow the ELF header
+        let mut body = Vec::new();
+        let mut offsets = Vec::new();
+        for section in sections {
+            offsets.push((EHDR_SIZE + body.len()) as u32);
+            if !section.nobits {
+                body.extend_from_slice(section.data);
+            }
+        }
+        let strtab_offset = (EHDR_SIZE + body.len()) as u32;
+        body.extend_from_slice(&strtab);
+        while body.len() % 4 != 0 {
+            body.push(0);
+        }
+        let shoff = EHDR_SIZE + body.len();
+        let shnum = sections.len() + 2;
+
+        let mut elf = vec![
+            0x7f,
+            b'E',
+            b'L',
+            b'F',
+            1,
+            if little_endian { 1 } else { 2 },
+            1,
+        ];
+        elf.resize(16, 0);
+        elf.extend_from_slice(&half(2)); // ET_EXEC
+        elf.extend_from_slice(&half(machine));
+        elf.extend_from_slice(&word(1));
+        elf.extend_from_slice(&word(entry));
+        elf.extend_from_slice(&word(0));
+        elf.extend_from_slice(&word(shoff as u32));
+        elf.extend_from_slice(&word(0));
+        elf.extend_from_slice(&half(EHDR_SIZE as u16));
+        elf.extend_from_slice(&half(0));
+        elf.extend_from_slice(&half(0));
+        elf.extend_from_slice(&half(SHDR_SIZE as u16));
+        elf.extend_from_slice(&half(shnum as u16));
+        elf.extend_from_slice(&half(shnum as u16 - 1));
+        elf.extend_from_slice(&body);
+
+        let mut section_header = |name, kind, flags, addr, offset, size: usize| {
+            for value in [name, kind, flags, addr, offset, size as u32, 0, 0, 4, 0] {
+                elf.extend_from_slice(&word(value));
+            }
+        };
+        section_header(0, 0, 0, 0, 0, 0);
+        for (i, section) in sections.iter().enumerate() {
+            let size = section.data.len();
+            let kind = if section.nobits {
+                SHT_NOBITS
+            } else {
+                SHT_PROGBITS
+            };
+            section_header(
+                names[i],
+                kind,
+                section.flags,
+                section.addr,
+                offsets[i],
+                size,
+            );
+        }
+        section_header(
+            names[sections.len()],
+            SHT_STRTAB,
+            0,
+            0,
+            strtab_offset,
+            strtab.len(),
+        );
+
+        elf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{build_elf, Section};
+    use super::*;
+    use crate::test_util::scratch_dir;
+    use goblin::elf::header::{EM_ARM, EM_MIPS};
+    use goblin::elf::section_header::{SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE};
+
+    const ENTRY: u32 = 0x8000_0400;
+
+    fn boot_section() -> Section<'static> {
+        Section {
+            name: ".boot",
+            addr: ENTRY,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            data: &[0; 16],
+            nobits: false,
+        }
+    }
+
+    fn bss_section(addr: u32) -> Section<'static> {
+        Section {
+            name: ".bss",
+            addr,
+            flags: SHF_ALLOC | SHF_WRITE,
+            data: &[0; 64],
+            nobits: true,
+        }
+    }
+
+    fn boot_elf(machine: u16, little_endian: bool) -> Vec<u8> {
+        build_elf(machine, little_endian, ENTRY, &[boot_section()])
+    }
+
+    #[test]
+    fn test_validation_strict_by_default() {
+        let options = DumpOptions::default();
+
+        assert!(dump_bytes(&boot_elf(EM_MIPS, false), &options).is_ok());
+        assert!(dump_bytes(&boot_elf(EM_MIPS, true), &options).is_err());
+        assert!(dump_bytes(&boot_elf(EM_ARM, false), &options).is_err());
+    }
+
+    #[test]
+    fn test_validation_skipped() {
+        let options = DumpOptions {
+            skip_validation: true,
+            ..DumpOptions::default()
+        };
+
+        assert!(dump_bytes(&boot_elf(EM_MIPS, true), &options).is_ok());
+        assert!(dump_bytes(&boot_elf(EM_ARM, false), &options).is_ok());
+    }
+
+    #[test]
+    fn test_little_endian_swapped() {
+        const WORDS: [u8; 8] = [0x3c, 0x1d, 0x80, 0x01, 0x27, 0xbd, 0x00, 0x10];
+        const SWAPPED: [u8; 8] = [0x01, 0x80, 0x1d, 0x3c, 0x10, 0x00, 0xbd, 0x27];
+
+        let big = Section {
+            data: &WORDS,
+            ..boot_section()
+        };
+        let little = Section {
+            data: &SWAPPED,
+            ..boot_section()
+        };
+        let big = build_elf(EM_MIPS, false, ENTRY, &[big]);
+        let little = build_elf(EM_MIPS, true, ENTRY, &[little]);
+        let options = DumpOptions {
+            endian: Endian::Little,
+            ..DumpOptions::default()
+        };
+
+        let big = dump_bytes(&big, &DumpOptions::default()).unwrap();
+        let little = dump_bytes(&little, &options).unwrap();
+
+        assert_eq!(big.program, WORDS);
+        assert_eq!(little.program, WORDS);
+    }
+
+    #[test]
+    fn test_little_endian_hint_mismatch() {
+        let options = DumpOptions {
+            endian: Endian::Little,
+            ..DumpOptions::default()
+        };
+
+        assert!(dump_bytes(&boot_elf(EM_MIPS, false), &options).is_err());
+    }
+
+    #[test]
+    fn test_boot_text_rodata_layout() {
+        let boot = Section {
+            data: &[1; 8],
+            ..boot_section()
+        };
+        let text = Section {
+            name: ".text",
+            addr: ENTRY + 8,
+            data: &[2; 12],
+            ..boot_section()
+        };
+        let rodata = Section {
+            name: ".rodata",
+            addr: ENTRY + 32,
+            flags: SHF_ALLOC,
+            data: &[3; 4],
+            nobits: false,
+        };
+        let elf = build_elf(EM_MIPS, false, ENTRY, &[boot, text, rodata]);
+
+        let dir = scratch_dir("layout");
+        let path = dir.join("layout.elf");
+        fs::write(&path, &elf).unwrap();
+        let dump = dump(&path.to_string_lossy(), &DumpOptions::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // .rodata is 12 bytes past the end of .text, the gap is zero-filled
+        let mut expected = vec![1; 8];
+        expected.extend_from_slice(&[2; 12]);
+        expected.extend_from_slice(&[0; 12]);
+        expected.extend_from_slice(&[3; 4]);
+        assert_eq!(dump.entry_point, ENTRY);
+        assert_eq!(dump.program, expected);
+        assert_eq!(dump.bss, None);
+    }
+
+    #[test]
+    fn test_boot_alignment() {
+        let aligned = build_elf(EM_MIPS, false, ENTRY, &[boot_section()]);
+        let misaligned_addr = Section {
+            addr: ENTRY + 2,
+            ..boot_section()
+        };
+        let misaligned_addr = build_elf(EM_MIPS, false, ENTRY + 2, &[misaligned_addr]);
+        let misaligned_size = Section {
+            data: &[0; 6],
+            ..boot_section()
+        };
+        let misaligned_size = build_elf(EM_MIPS, false, ENTRY, &[misaligned_size]);
+
+        assert!(dump_bytes(&aligned, &DumpOptions::default()).is_ok());
+
+        let error = dump_bytes(&misaligned_addr, &DumpOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Dump error: .boot section at 0x80000402 with size 0x10 must be word-aligned"
+        );
+        assert!(dump_bytes(&misaligned_size, &DumpOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_overlapping_sections() {
+        let text = Section {
+            name: ".text",
+            addr: ENTRY + 8,
+            ..boot_section()
+        };
+        let elf = build_elf(EM_MIPS, false, ENTRY, &[boot_section(), text]);
+
+        let error = dump_bytes(&elf, &DumpOptions::default()).err().unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "Dump error: .text section at 0x80000408 overlaps .boot section ending at 0x80000410"
+        );
+    }
+
+    #[test]
+    fn test_large_gap_zero_filled() {
+        let text = Section {
+            name: ".text",
+            addr: ENTRY + 0x20_0000,
+            ..boot_section()
+        };
+        let elf = build_elf(EM_MIPS, false, ENTRY, &[boot_section(), text]);
+        let options = DumpOptions {
+            verbose: 1,
+            ..DumpOptions::default()
+        };
+
+        let dump = dump_bytes(&elf, &options).unwrap();
+
+        assert_eq!(dump.program.len(), 0x20_0000 + 16);
+        assert_eq!(
+            check_gap(0x20_0000 - 16, ".boot", ".text").unwrap(),
+            "Zero-filling 0x1ffff0 bytes between .boot and .text sections"
+        );
+        assert_eq!(check_gap(MAX_GAP, ".boot", ".text"), None);
+    }
+
+    #[test]
+    fn test_verbose_output_unchanged() {
+        let text = Section {
+            name: ".text",
+            addr: ENTRY + 32,
+            ..boot_section()
+        };
+        let elf = build_elf(EM_MIPS, false, ENTRY, &[boot_section(), text]);
+        let options = DumpOptions {
+            verbose: 2,
+            ..DumpOptions::default()
+        };
+
+        let quiet = dump_bytes(&elf, &DumpOptions::default()).unwrap();
+        let verbose = dump_bytes(&elf, &options).unwrap();
+
+        assert_eq!(verbose.program, quiet.program);
+        assert_eq!(verbose.program.len(), 48);
+
+        // The .text section follows a 16 byte gap after .boot
+        let parsed = Elf::parse(&elf).unwrap();
+        assert_eq!(
+            format_section(".boot", find_section(&parsed, ".boot").unwrap()),
+            ".boot at 0x80000400, 0x10 bytes"
+        );
+        assert_eq!(
+            format_zero_fill(16, u64::from(ENTRY) + 16),
+            "0x10 bytes at 0x80000410"
+        );
+        assert_eq!(
+            format_section(".text", find_section(&parsed, ".text").unwrap()),
+            ".text at 0x80000420, 0x10 bytes"
+        );
+    }
+
+    #[test]
+    fn test_custom_sections() {
+        let ramfuncs = Section {
+            name: ".ramfuncs",
+            addr: ENTRY + 16,
+            data: &[1; 8],
+            ..boot_section()
+        };
+        let elf = build_elf(EM_MIPS, false, ENTRY, &[boot_section(), ramfuncs]);
+        let options = DumpOptions {
+            sections: vec![".ramfuncs".into(), ".missing".into()],
+            ..DumpOptions::default()
+        };
+
+        let default = dump_bytes(&elf, &DumpOptions::default()).unwrap();
+        let custom = dump_bytes(&elf, &options).unwrap();
+
+        assert_eq!(default.program.len(), 16);
+        assert_eq!(&custom.program[16..], &[1; 8]);
+    }
+
+    #[test]
+    fn test_bss_extent() {
+        let elf = build_elf(
+            EM_MIPS,
+            false,
+            ENTRY,
+            &[boot_section(), bss_section(ENTRY + 16)],
+        );
+
+        let dump = dump_bytes(&elf, &DumpOptions::default()).unwrap();
+
+        assert_eq!(dump.program.len(), 16);
+        assert_eq!(dump.bss, Some(ENTRY + 16..ENTRY + 80));
+    }
+
+    #[test]
+    fn test_bss_order_and_gap() {
+        let sbss = Section {
+            name: ".sbss",
+            ..bss_section(ENTRY + 96)
+        };
+        let elf = build_elf(
+            EM_MIPS,
+            false,
+            ENTRY,
+            &[boot_section(), sbss, bss_section(ENTRY + 16)],
+        );
+
+        let dump = dump_bytes(&elf, &DumpOptions::default()).unwrap();
+
+        // .bss comes first, and .sbss follows a 16 byte gap
+        assert_eq!(dump.bss, Some(ENTRY + 16..ENTRY + 160));
+    }
+
+    #[test]
+    fn test_bss_not_contiguous() {
+        let gap = build_elf(
+            EM_MIPS,
+            false,
+            ENTRY,
+            &[boot_section(), bss_section(ENTRY + 256)],
+        );
+        let overlap = build_elf(
+            EM_MIPS,
+            false,
+            ENTRY,
+            &[boot_section(), bss_section(ENTRY + 8)],
+        );
+
+        assert!(dump_bytes(&gap, &DumpOptions::default()).is_err());
+        assert!(dump_bytes(&overlap, &DumpOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use thiserror::Error;
+
+/// Environment variable holding the emulator command when `--emulator` is not given
+const EMULATOR_VAR: &str = "CARGO_N64_EMULATOR";
+
+/// Placeholder in the emulator command that is replaced with the ROM path
+const ROM_PLACEHOLDER: &str = "{rom}";
+
+#[derive(Debug, Error)]
+pub enum EmulatorError {
+    #[error("No emulator configured, use `--emulator` or set `{}`", EMULATOR_VAR)]
+    MissingEmulator,
+
+    #[error("Empty emulator command")]
+    EmptyCommand,
+
+    #[error("Cannot run a ROM written to stdout")]
+    StdoutRom,
+
+    #[error("Cannot run more than one ROM, select one with `--package`")]
+    AmbiguousRom,
+
+    #[error("Failed to launch the emulator")]
+    Io(#[from] io::Error),
+
+    #[error("Emulator {}", exit_status(.0))]
+    Exit(Option<i32>),
+}
+
+/// Describe how the emulator exited, without a code when it was killed by a signal.
+fn exit_status(code: &Option<i32>) -> String {
+    match code {
+        Some(code) => format!("exited with code: {}", code),
+        None => "was terminated by a signal or exited with an unknown status".to_owned(),
+    }
+}
+
+/// Resolve the emulator command template from `--emulator` or the environment.
+pub(crate) fn command(emulator: Option<&str>) -> Result<String, EmulatorError> {
+    match emulator {
+        Some(emulator) => Ok(emulator.to_owned()),
+        None => env::var(EMULATOR_VAR).map_err(|_| EmulatorError::MissingEmulator),
+    }
+}
+
+/// Split the command template on whitespace and substitute the ROM path. The
+/// path is appended as the last argument when the template has no placeholder.
+fn command_line(template: &str, rom: &Path) -> Result<Vec<String>, EmulatorError> {
+    let rom = rom.to_string_lossy();
+    let mut args: Vec<_> = template
+        .split_whitespace()
+        .map(|arg| arg.replace(ROM_PLACEHOLDER, &rom))
+        .collect();
+
+    if args.is_empty() {
+        return Err(EmulatorError::EmptyCommand);
+    }
+    if !template.contains(ROM_PLACEHOLDER) {
+        args.push(rom.into_owned());
+    }
+
+    Ok(args)
+}
+
+/// Launch the emulator with `rom` and wait for it to exit.
+pub(crate) fn run(template: &str, rom: &Path, verbose: usize) -> Result<(), EmulatorError> {
+    let args = command_line(template, rom)?;
+
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+    if verbose > 0 {
+        eprintln!("+ {:?}", command);
+    }
+
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(EmulatorError::Exit(status.code()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_line, EmulatorError};
+    use std::path::Path;
+
+    #[test]
+    fn test_command_line() {
+        let rom = Path::new("target/hello.n64");
+
+        assert_eq!(
+            command_line("ares --system n64 {rom}", rom).unwrap(),
+            ["ares", "--system", "n64", "target/hello.n64"]
+        );
+        assert_eq!(
+            command_line("cen64 pifdata.bin", rom).unwrap(),
+            ["cen64", "pifdata.bin", "target/hello.n64"]
+        );
+        assert!(command_line("  ", rom).is_err());
+    }
+
+    #[test]
+    fn test_exit_message() {
+        assert_eq!(
+            EmulatorError::Exit(Some(1)).to_string(),
+            "Emulator exited with code: 1"
+        );
+        assert_eq!(
+            EmulatorError::Exit(None).to_string(),
+            "Emulator was terminated by a signal or exited with an unknown status"
+        );
+    }
+}
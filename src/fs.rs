@@ -1,9 +1,77 @@
-use fatfs::{self, FileSystem, FormatVolumeOptions, FsOptions};
+use fatfs::{self, Date, DateTime, FileSystem, FormatVolumeOptions, FsOptions, Time, TimeProvider};
+use std::collections::HashMap;
 use std::fs::{self, metadata, read_dir, DirEntry};
 use std::io::{self, Cursor, Write};
-use std::path::{Path, StripPrefixError};
+use std::path::{Path, PathBuf, StripPrefixError};
 use thiserror::Error;
 
+/// Default maximum directory nesting depth for the embedded file system
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// Default FAT volume label
+pub(crate) const DEFAULT_LABEL: [u8; 11] = *b"TECHNEKDISK";
+
+/// Options controlling how the embedded file system is built.
+#[derive(Debug)]
+pub(crate) struct FSOptions {
+    /// Maximum directory nesting depth
+    pub(crate) max_depth: usize,
+
+    /// FAT volume label, padded with spaces
+    pub(crate) label: [u8; 11],
+
+    /// Glob patterns for entries to leave out, matched against the entry name
+    /// and its path relative to the root
+    pub(crate) exclude: Vec<String>,
+
+    /// Include entries whose name starts with a dot
+    pub(crate) dotfiles: bool,
+
+    /// Bytes reserved for FAT, computed from the number of entries by default
+    pub(crate) reserved: Option<usize>,
+}
+
+impl Default for FSOptions {
+    fn default() -> Self {
+        FSOptions {
+            max_depth: DEFAULT_MAX_DEPTH,
+            label: DEFAULT_LABEL,
+            exclude: Vec::new(),
+            dotfiles: false,
+            reserved: None,
+        }
+    }
+}
+
+/// Stamps every file and directory with the FAT epoch, so that building the
+/// same directory twice produces identical images.
+#[derive(Debug)]
+struct FixedTimeProvider;
+
+static FIXED_TIME: FixedTimeProvider = FixedTimeProvider;
+
+impl TimeProvider for FixedTimeProvider {
+    fn get_current_date(&self) -> Date {
+        Date {
+            year: 1980,
+            month: 1,
+            day: 1,
+        }
+    }
+
+    fn get_current_date_time(&self) -> DateTime {
+        DateTime {
+            date: self.get_current_date(),
+            time: Time {
+                hour: 0,
+                min: 0,
+                sec: 0,
+                millis: 0,
+            },
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum FSError {
     #[error("IO Error")]
@@ -14,15 +82,70 @@ pub enum FSError {
 
     #[error("Missing file name")]
     MissingFileName,
+
+    #[error("Maximum directory depth of {1} exceeded at `{0}`")]
+    MaxDepthExceeded(String, usize),
+
+    #[error("Symlink loop detected at `{0}`")]
+    SymlinkLoop(String),
+
+    #[error("Unable to format a volume of {1} bytes, try a larger `--fs-reserved`")]
+    FormatError(#[source] io::Error, usize),
+
+    #[error("File `{0}` listed in `fs.order` is missing or excluded")]
+    OrderedFileError(String),
+
+    #[error("Path `{0}` exists in both `{1}` and `{2}`")]
+    PathCollision(String, String, String),
 }
 
+/// Visit every entry below `path` in a stable order, skipping excluded entries.
 fn traverse<T>(
     path: &impl AsRef<Path>,
+    options: &FSOptions,
+    acc: T,
+    cb: &impl Fn(T, &DirEntry) -> Result<T, FSError>,
+) -> Result<T, FSError> {
+    let root = path.as_ref();
+
+    traverse_dir(root, root, 0, options, &mut Vec::new(), acc, cb)
+}
+
+fn traverse_dir<T>(
+    root: &Path,
+    path: &Path,
+    depth: usize,
+    options: &FSOptions,
+    ancestors: &mut Vec<PathBuf>,
     mut acc: T,
     cb: &impl Fn(T, &DirEntry) -> Result<T, FSError>,
 ) -> Result<T, FSError> {
-    for entry in read_dir(path)? {
-        let entry = entry?;
+    use self::FSError::*;
+
+    if depth > options.max_depth {
+        return Err(MaxDepthExceeded(
+            path.to_string_lossy().to_string(),
+            options.max_depth,
+        ));
+    }
+
+    // Directories are identified by their canonical path, so a symlink pointing
+    // back to one of its own ancestors is caught instead of recursing forever.
+    let canonical = path.canonicalize()?;
+    if ancestors.contains(&canonical) {
+        return Err(SymlinkLoop(path.to_string_lossy().to_string()));
+    }
+    ancestors.push(canonical);
+
+    // Visit entries in a stable order, independent of the host file system
+    let mut entries = read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        // Excluded directories are skipped with all of their contents
+        if is_excluded(root, &entry.path(), options)? {
+            continue;
+        }
 
         // Accumulate
         acc = cb(acc, &entry)?;
@@ -30,60 +153,485 @@ fn traverse<T>(
         // Recursively call into directories and accumulate
         let path = entry.path();
         if path.is_dir() {
-            acc = traverse(&path, acc, cb)?;
+            acc = traverse_dir(root, &path, depth + 1, options, ancestors, acc, cb)?;
         }
     }
+
+    ancestors.pop();
+
     Ok(acc)
 }
 
-pub(crate) fn create_filesystem(fs_path: impl AsRef<Path>) -> Result<Vec<u8>, FSError> {
-    // Make sure the path is normalized to absolute.
-    let fs_path = fs_path.as_ref().canonicalize()?;
+/// Whether `path` is a dotfile, the order manifest, or matches any of the
+/// exclusion patterns by name or by its path relative to `root`.
+fn is_excluded(root: &Path, path: &Path, options: &FSOptions) -> Result<bool, FSError> {
+    let name = path.file_name().ok_or(FSError::MissingFileName)?;
+    let name = name.to_string_lossy();
+    if !options.dotfiles && name.starts_with('.') {
+        return Ok(true);
+    }
+
+    let relative = relative_name(root, path)?;
+    if relative == ORDER_MANIFEST {
+        return Ok(true);
+    }
+
+    Ok(options
+        .exclude
+        .iter()
+        .any(|pattern| glob_match(pattern, &name) || glob_match(pattern, &relative)))
+}
+
+/// The path of `path` relative to `root`, with `/` separators.
+fn relative_name(root: &Path, path: &Path) -> Result<String, FSError> {
+    Ok(path
+        .strip_prefix(root)?
+        .to_string_lossy()
+        .replace('\\', "/"))
+}
 
-    // Minimum number of bytes reserved for FAT
-    // FIXME: Is this enough in general?
-    const RESERVED_BYTES: usize = 128 * 1024;
+/// Match `text` against a glob `pattern`. `*` matches any run of characters and
+/// `?` matches a single character, neither matches a path separator.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match (pattern.first(), text.first()) {
+            (Some('*'), _) => {
+                matches(&pattern[1..], text)
+                    || (text.first().map_or(false, |&c| c != '/') && matches(pattern, &text[1..]))
+            }
+            (Some('?'), Some(&c)) if c != '/' => matches(&pattern[1..], &text[1..]),
+            (Some(p), Some(c)) if p == c => matches(&pattern[1..], &text[1..]),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    let pattern: Vec<_> = pattern.chars().collect();
+    let text: Vec<_> = text.chars().collect();
+
+    matches(&pattern, &text)
+}
+
+/// Optional manifest in the file system root, listing relative paths of files
+/// to write first, in order. The manifest itself is not part of the image.
+const ORDER_MANIFEST: &str = "fs.order";
+
+/// Read the order manifest in `fs_path`, if any. Blank lines and lines starting
+/// with `#` are ignored. Every listed path must be an included file.
+fn read_order(fs_path: &Path, options: &FSOptions) -> Result<Vec<String>, FSError> {
+    let manifest = fs_path.join(ORDER_MANIFEST);
+    if !manifest.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let mut order = Vec::new();
+    for line in fs::read_to_string(manifest)?.lines() {
+        let name = line.trim().trim_start_matches('/');
+        if name.is_empty() || name.starts_with('#') {
+            continue;
+        }
+
+        // The file and each of its parent directories must be included
+        let path = fs_path.join(name);
+        let mut included = path.is_file();
+        for ancestor in path.ancestors().take_while(|&p| p != fs_path) {
+            included = included && !is_excluded(fs_path, ancestor, options)?;
+        }
+        if !included {
+            return Err(FSError::OrderedFileError(name.to_owned()));
+        }
+
+        order.push(name.to_owned());
+    }
+
+    Ok(order)
+}
+
+/// Minimum number of bytes reserved for FAT
+const RESERVED_BYTES: usize = 128 * 1024;
+
+/// Additional bytes reserved per file, for directory entries (including long
+/// file names) and FAT entries
+const RESERVED_PER_FILE: usize = 256;
+
+/// Additional bytes reserved per directory, which occupies at least one cluster
+const RESERVED_PER_DIR: usize = 4 * 1024;
+
+/// Default number of bytes reserved for FAT, scaled by the number of entries.
+fn default_reserved(files: usize, dirs: usize) -> usize {
+    RESERVED_BYTES + files * RESERVED_PER_FILE + dirs * RESERVED_PER_DIR
+}
+
+/// Compute the volume size required to hold every file in `fs_paths`, with each
+/// file rounded up to a whole number of 512-byte sectors. Directories merged
+/// from several roots are counted once per root.
+fn volume_size(fs_paths: &[impl AsRef<Path>], options: &FSOptions) -> Result<usize, FSError> {
+    let mut acc = (0, 0, 0);
+    for fs_path in fs_paths {
+        acc = traverse(fs_path, options, acc, &|acc, entry| {
+            let (size, files, dirs) = acc;
+            let stat = metadata(&entry.path())?;
+            if stat.is_file() {
+                Ok((size + ((stat.len() as usize + 511) & !511), files + 1, dirs))
+            } else {
+                Ok((size, files, dirs + 1))
+            }
+        })?;
+    }
+    let (size, files, dirs) = acc;
+    let reserved = options
+        .reserved
+        .unwrap_or_else(|| default_reserved(files, dirs));
+
+    Ok(reserved + size)
+}
+
+/// Directories with the same path in several roots are merged, but any other
+/// entry may only exist in one of them. FAT names are case-insensitive, so are
+/// the paths compared here.
+fn check_collisions(fs_paths: &[PathBuf], options: &FSOptions) -> Result<(), FSError> {
+    let mut seen = HashMap::new();
+    for fs_path in fs_paths {
+        seen = traverse(fs_path, options, seen, &|mut seen, entry| {
+            let path = entry.path();
+            let name = relative_name(fs_path, &path)?;
+            let is_dir = path.is_dir();
+
+            match seen.insert(name.to_lowercase(), (fs_path, is_dir)) {
+                Some((other, other_is_dir)) if !(is_dir && other_is_dir) => {
+                    Err(FSError::PathCollision(
+                        name,
+                        other.to_string_lossy().to_string(),
+                        fs_path.to_string_lossy().to_string(),
+                    ))
+                }
+                _ => Ok(seen),
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Create a FAT image holding the contents of every directory in `fs_paths`,
+/// merged into a single root.
+pub(crate) fn create_filesystem(
+    fs_paths: &[impl AsRef<Path>],
+    options: &FSOptions,
+) -> Result<Vec<u8>, FSError> {
+    // Make sure the paths are normalized to absolute.
+    let fs_paths = fs_paths
+        .iter()
+        .map(|fs_path| fs_path.as_ref().canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    check_collisions(&fs_paths, options)?;
 
     // Compute the required volume size
     // WARNING: This is not atomic! Any changes to the file system after this
     // computation starts will surely break things later!
-    let size = traverse(&fs_path, RESERVED_BYTES, &|mut size, entry| {
-        let stat = metadata(&entry.path())?;
-        if stat.is_file() {
-            size += (stat.len() as usize + 511) & !512;
-        }
-        Ok(size)
-    })?;
+    let size = volume_size(&fs_paths, options)?;
 
     // Create a new in-memory volume
     let mut stream = Cursor::new(vec![0; size]);
     let opts = {
         let opts = FormatVolumeOptions::new();
-        opts.volume_label(*b"TECHNEKDISK")
+        opts.volume_label(options.label)
     };
-    fatfs::format_volume(&mut stream, opts)?;
+    fatfs::format_volume(&mut stream, opts).map_err(|e| FSError::FormatError(e, size))?;
 
     // This scope allows us to consume `stream` without explicitly dropping `disk`
     {
-        let disk = FileSystem::new(&mut stream, FsOptions::new())?;
+        let disk = FileSystem::new(&mut stream, FsOptions::new().time_provider(&FIXED_TIME))?;
         let root_dir = disk.root_dir();
 
-        // Traverse the directory again, this time copying file contents and creating directories.
-        traverse(&fs_path, (), &|(), entry| {
-            let path = entry.path();
-            let name = &path.strip_prefix(&fs_path)?.to_string_lossy();
+        // Files listed in the order manifests come first, so they get the lowest clusters
+        let orders = fs_paths
+            .iter()
+            .map(|fs_path| read_order(fs_path, options))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (fs_path, order) in fs_paths.iter().zip(&orders) {
+            for name in order {
+                let parents: Vec<_> = Path::new(name).ancestors().skip(1).collect();
+                for parent in parents.iter().rev().skip(1) {
+                    root_dir.create_dir(&parent.to_string_lossy())?;
+                }
 
-            if entry.file_type()?.is_dir() {
-                root_dir.create_dir(name)?;
-            } else {
-                let buffer = fs::read(&path)?;
+                let buffer = fs::read(fs_path.join(name))?;
                 let mut dest = root_dir.create_file(name)?;
                 dest.write_all(&buffer)?;
             }
+        }
 
-            Ok(())
-        })?;
+        // Traverse the directories again, this time copying file contents and creating directories.
+        // The same options are used, so exactly the entries counted above are written.
+        for (fs_path, order) in fs_paths.iter().zip(&orders) {
+            traverse(fs_path, options, (), &|(), entry| {
+                let path = entry.path();
+                let name = &relative_name(fs_path, &path)?;
+
+                // Follow symlinks, as `traverse` does
+                if metadata(&path)?.is_dir() {
+                    root_dir.create_dir(name)?;
+                } else if !order.contains(name) {
+                    let buffer = fs::read(&path)?;
+                    let mut dest = root_dir.create_file(name)?;
+                    dest.write_all(&buffer)?;
+                }
+
+                Ok(())
+            })?;
+        }
     }
 
     Ok(stream.into_inner())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_dir;
+
+    #[test]
+    fn test_traverse_max_depth() {
+        let root = scratch_dir("max-depth");
+        let nested = (0..4).fold(root.clone(), |path, i| path.join(format!("dir{}", i)));
+        fs::create_dir_all(&nested).unwrap();
+
+        let options = |max_depth| FSOptions {
+            max_depth,
+            ..FSOptions::default()
+        };
+
+        let shallow = traverse(&root, &options(2), 0, &|count, _| Ok(count + 1));
+        let deep = traverse(&root, &options(4), 0, &|count, _| Ok(count + 1));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(matches!(shallow, Err(FSError::MaxDepthExceeded(_, 2))));
+        assert_eq!(deep.unwrap(), 4);
+    }
+
+    #[test]
+    fn test_volume_size_sector_aligned() {
+        let root = scratch_dir("volume-size");
+        for size in [1, 511, 512, 513] {
+            fs::write(root.join(format!("{}.bin", size)), vec![0; size]).unwrap();
+        }
+
+        let size = volume_size(&[&root], &FSOptions::default());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            size.unwrap(),
+            default_reserved(4, 0) + 512 + 512 + 512 + 1024
+        );
+    }
+
+    #[test]
+    fn test_volume_size_reserved() {
+        let root = scratch_dir("volume-reserved");
+        fs::create_dir(root.join("data")).unwrap();
+        fs::write(root.join("data").join("level.bin"), [1; 512]).unwrap();
+        let options = FSOptions {
+            reserved: Some(1024 * 1024),
+            ..FSOptions::default()
+        };
+
+        let default = volume_size(&[&root], &FSOptions::default());
+        let reserved = volume_size(&[&root], &options);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(default.unwrap(), RESERVED_BYTES + 256 + 4 * 1024 + 512);
+        assert_eq!(reserved.unwrap(), 1024 * 1024 + 512);
+    }
+
+    #[test]
+    fn test_create_filesystem_reproducible() {
+        let root = scratch_dir("reproducible");
+        fs::create_dir(root.join("data")).unwrap();
+        fs::write(root.join("data").join("level.bin"), [1; 1000]).unwrap();
+        fs::write(root.join("readme.txt"), b"hello").unwrap();
+
+        let first = create_filesystem(&[&root], &FSOptions::default()).unwrap();
+        let second = create_filesystem(&[&root], &FSOptions::default()).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert!(first == second);
+
+        // Every entry is stamped with the FAT epoch, not the current time
+        let is_epoch = |date: Date| (date.year, date.month, date.day) == (1980, 1, 1);
+        let disk = FileSystem::new(Cursor::new(first), FsOptions::new()).unwrap();
+        let mut dirs = vec![disk.root_dir()];
+        let mut entries = 0;
+        while let Some(dir) = dirs.pop() {
+            for entry in dir.iter() {
+                let entry = entry.unwrap();
+                if entry.file_name() == "." || entry.file_name() == ".." {
+                    continue;
+                }
+
+                for date_time in [entry.created(), entry.modified()] {
+                    let time = date_time.time;
+                    assert!(is_epoch(date_time.date), "{}", entry.file_name());
+                    assert_eq!((time.hour, time.min, time.sec), (0, 0, 0));
+                }
+                assert!(is_epoch(entry.accessed()), "{}", entry.file_name());
+
+                entries += 1;
+                if entry.is_dir() {
+                    dirs.push(entry.to_dir());
+                }
+            }
+        }
+        assert_eq!(entries, 3);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.swp", "main.rs.swp"));
+        assert!(glob_match("level?.bin", "level1.bin"));
+        assert!(glob_match("assets/*", "assets/raw"));
+        assert!(!glob_match("*.swp", "data/main.swp"));
+        assert!(!glob_match("level?.bin", "level10.bin"));
+    }
+
+    #[test]
+    fn test_traverse_exclude() {
+        let root = scratch_dir("exclude");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("data")).unwrap();
+        for file in [
+            ".DS_Store",
+            ".git/HEAD",
+            "a.txt",
+            "b.swp",
+            "data/c.swp",
+            "data/d.bin",
+        ] {
+            fs::write(root.join(file), b"x").unwrap();
+        }
+        let options = FSOptions {
+            exclude: vec!["*.swp".into()],
+            ..FSOptions::default()
+        };
+
+        let names = traverse(&root, &options, Vec::new(), &|mut names, entry| {
+            let path = entry.path();
+            names.push(
+                path.strip_prefix(&root)?
+                    .to_string_lossy()
+                    .replace('\\', "/"),
+            );
+            Ok(names)
+        });
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(names.unwrap(), ["a.txt", "data", "data/d.bin"]);
+    }
+
+    #[test]
+    fn test_create_filesystem_order() {
+        let root = scratch_dir("order");
+        fs::create_dir(root.join("z")).unwrap();
+        fs::write(root.join("a.bin"), [0xa1; 512]).unwrap();
+        fs::write(root.join("b.bin"), [0xb2; 512]).unwrap();
+        fs::write(root.join("z").join("first.bin"), [0xc3; 512]).unwrap();
+        fs::write(
+            root.join(ORDER_MANIFEST),
+            "# Load order\nz/first.bin\nb.bin\n",
+        )
+        .unwrap();
+
+        let image = create_filesystem(&[&root], &FSOptions::default()).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let offset = |byte| {
+            image
+                .windows(512)
+                .position(|window| window.iter().all(|&b| b == byte))
+                .unwrap()
+        };
+        assert!(offset(0xc3) < offset(0xb2));
+        assert!(offset(0xb2) < offset(0xa1));
+        assert!(!image.windows(11).any(|window| window == b"z/first.bin"));
+    }
+
+    #[test]
+    fn test_order_missing_file() {
+        let root = scratch_dir("order-missing");
+        fs::write(root.join(".hidden"), b"x").unwrap();
+        fs::write(root.join(ORDER_MANIFEST), "missing.bin\n").unwrap();
+        let missing = read_order(&root, &FSOptions::default());
+        fs::write(root.join(ORDER_MANIFEST), ".hidden\n").unwrap();
+        let excluded = read_order(&root, &FSOptions::default());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(matches!(missing, Err(FSError::OrderedFileError(_))));
+        assert!(matches!(excluded, Err(FSError::OrderedFileError(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_traverse_symlink_loop() {
+        let root = scratch_dir("symlink-loop");
+        fs::create_dir(root.join("dir")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("dir").join("loop")).unwrap();
+
+        let result = traverse(&root, &FSOptions::default(), (), &|(), _| Ok(()));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(matches!(result, Err(FSError::SymlinkLoop(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_filesystem_symlinked_dir() {
+        use std::io::Read;
+
+        let root = scratch_dir("symlink-dir");
+        fs::create_dir_all(root.join("assets")).unwrap();
+        fs::create_dir_all(root.join("fs")).unwrap();
+        fs::write(root.join("assets").join("level.bin"), [7; 100]).unwrap();
+        std::os::unix::fs::symlink(root.join("assets"), root.join("fs").join("linked")).unwrap();
+
+        let image = create_filesystem(&[root.join("fs")], &FSOptions::default());
+        fs::remove_dir_all(&root).unwrap();
+
+        let disk = FileSystem::new(Cursor::new(image.unwrap()), FsOptions::new()).unwrap();
+        let mut contents = Vec::new();
+        disk.root_dir()
+            .open_file("linked/level.bin")
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, [7; 100]);
+    }
+
+    #[test]
+    fn test_create_filesystem_merged() {
+        let root = scratch_dir("merged");
+        for dir in ["a/data", "b/data", "c/data", "d/Data"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("a/data/static.bin"), [0xa1; 512]).unwrap();
+        fs::write(root.join("b/data/generated.bin"), [0xb2; 512]).unwrap();
+        fs::write(root.join("c/data/static.bin"), [0xc3; 512]).unwrap();
+        fs::write(root.join("d/Data/Static.BIN"), [0xd4; 512]).unwrap();
+
+        let merged = create_filesystem(&[root.join("a"), root.join("b")], &FSOptions::default());
+        let size = volume_size(&[root.join("a"), root.join("b")], &FSOptions::default());
+        let collision = create_filesystem(&[root.join("a"), root.join("c")], &FSOptions::default());
+        let case_collision =
+            create_filesystem(&[root.join("a"), root.join("d")], &FSOptions::default());
+        fs::remove_dir_all(&root).unwrap();
+
+        let merged = merged.unwrap();
+        assert!(merged.windows(512).any(|w| w.iter().all(|&b| b == 0xa1)));
+        assert!(merged.windows(512).any(|w| w.iter().all(|&b| b == 0xb2)));
+        assert_eq!(size.unwrap(), default_reserved(2, 2) + 512 + 512);
+        assert!(
+            matches!(collision, Err(FSError::PathCollision(name, _, _)) if name == "data/static.bin")
+        );
+        assert!(
+            matches!(case_collision, Err(FSError::PathCollision(name, _, _)) if name == "Data/Static.BIN")
+        );
+    }
+}
@@ -1,8 +1,71 @@
-use crate::ipl3::IPL3;
+use crate::RomOptions;
+use std::convert::TryInto;
+use thiserror::Error;
 
 pub(crate) const HEADER_SIZE: usize = 0x40;
 
-#[derive(Debug, Clone, Copy)]
+/// Offset of the CRC1 and CRC2 fields within the header
+pub(crate) const CRC_OFFSET: usize = 0x10;
+
+#[derive(Debug, Error)]
+pub enum HeaderError {
+    #[error("Header is too small, expected {} bytes, found {0}", HEADER_SIZE)]
+    TooSmall(usize),
+}
+
+/// Cartridge timing and clock rate fields at the start of the header.
+///
+/// The four PI domain 1 bytes are loaded by the PIF to configure cartridge
+/// access timing before the IPL3 runs. The defaults are the values used by
+/// retail cartridges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderOptions {
+    /// PI_BSD_DOM1_LAT_REG
+    pub device_latency: u8,
+
+    /// PI_BSD_DOM1_PWD_REG
+    pub device_rw_pulse_width: u8,
+
+    /// PI_BSD_DOM1_PGS_REG
+    pub device_page_size: u8,
+
+    /// PI_BSD_DOM1_RLS_REG
+    pub device_rw_release_duration: u8,
+
+    /// Clock rate override, unused by IPL and OS
+    pub clock_rate: u32,
+}
+
+impl Default for HeaderOptions {
+    fn default() -> HeaderOptions {
+        HeaderOptions {
+            device_latency: 128,
+            device_rw_pulse_width: 55,
+            device_page_size: 18,
+            device_rw_release_duration: 64,
+            clock_rate: 15,
+        }
+    }
+}
+
+/// Maximum length of the program name in the header
+pub(crate) const NAME_SIZE: usize = 20;
+
+/// Pad the program name with spaces, or truncate it on a character boundary
+/// when it is longer than the header field.
+pub(crate) fn header_name(name: &str) -> [u8; NAME_SIZE] {
+    let mut len = name.len().min(NAME_SIZE);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    let mut header_name = [b' '; NAME_SIZE];
+    header_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+    header_name
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct N64Header {
     // 0x00
     device_latency: u8,             // PI_BSD_DOM1_LAT_REG
@@ -19,7 +82,7 @@ pub(crate) struct N64Header {
     _reserved_1: [u8; 8],
 
     // 0x20
-    name: [u8; 20],
+    name: [u8; NAME_SIZE],
     _reserved_2: [u8; 7],
     manufacturer: u8,
     cart_id: [u8; 2],
@@ -30,31 +93,24 @@ pub(crate) struct N64Header {
 impl N64Header {
     pub(crate) fn new(
         entry_point: u32,
-        name_str: &str,
         program: &[u8],
         fs: &[u8],
-        ipl3: &IPL3,
+        options: &RomOptions,
     ) -> N64Header {
-        let (crc1, crc2) = ipl3.compute_crcs(program, fs);
+        let ipl3 = &options.ipl3;
+        let (crc1, crc2) = ipl3.compute_crcs_padded(program, fs, options.pad_byte);
         let entry_point = ipl3.offset(entry_point);
 
-        let name_str = format!("{:20}", name_str);
-        let mut name = [0; 20];
-        name.copy_from_slice(name_str.as_bytes());
-        let name = name;
-
-        let cart_id_str = b"KW"; // KodeWerx!
-        let mut cart_id = [0; 2];
-        cart_id.copy_from_slice(cart_id_str);
-        let cart_id = cart_id;
+        let name = header_name(&options.name);
+        let header = &options.header;
 
         N64Header {
             // 0x00
-            device_latency: 128,
-            device_rw_pulse_width: 55,
-            device_page_size: 18,
-            device_rw_release_duration: 64,
-            clock_rate: 15,
+            device_latency: header.device_latency,
+            device_rw_pulse_width: header.device_rw_pulse_width,
+            device_page_size: header.device_page_size,
+            device_rw_release_duration: header.device_rw_release_duration,
+            clock_rate: header.clock_rate,
             entry_point,
             release: 0,
 
@@ -67,12 +123,59 @@ impl N64Header {
             name,
             _reserved_2: [0; 7],
             manufacturer: b'N', // Nintendo
-            cart_id,
-            region_code: b'E', // USA/English
+            cart_id: options.cart_id,
+            region_code: options.region,
             _reserved_3: 0,
         }
     }
 
+    /// Parse the header at the start of `bytes`, which may be a complete ROM.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<N64Header, HeaderError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(HeaderError::TooSmall(bytes.len()));
+        }
+
+        let word =
+            |offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        fn array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+            bytes[offset..offset + N].try_into().unwrap()
+        }
+
+        Ok(N64Header {
+            // 0x00
+            device_latency: bytes[0x00],
+            device_rw_pulse_width: bytes[0x01],
+            device_page_size: bytes[0x02],
+            device_rw_release_duration: bytes[0x03],
+            clock_rate: word(0x04),
+            entry_point: word(0x08),
+            release: word(0x0c),
+
+            // 0x10
+            crc1: word(CRC_OFFSET),
+            crc2: word(CRC_OFFSET + 4),
+            _reserved_1: array(bytes, 0x18),
+
+            // 0x20
+            name: array(bytes, 0x20),
+            _reserved_2: array(bytes, 0x34),
+            manufacturer: bytes[0x3b],
+            cart_id: array(bytes, 0x3c),
+            region_code: bytes[0x3e],
+            _reserved_3: bytes[0x3f],
+        })
+    }
+
+    /// The entry point field, offset for the IPL3.
+    pub(crate) fn entry_point(&self) -> u32 {
+        self.entry_point
+    }
+
+    /// The CRC1 and CRC2 fields.
+    pub(crate) fn crcs(&self) -> (u32, u32) {
+        (self.crc1, self.crc2)
+    }
+
     pub(crate) fn to_vec(self) -> Vec<u8> {
         // 0x00
         let mut buffer = vec![
@@ -101,3 +204,53 @@ impl N64Header {
         buffer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{header_name, HeaderOptions, N64Header, HEADER_SIZE};
+    use crate::{RomOptions, IPL3, IPL_SIZE};
+
+    #[test]
+    fn test_header_name() {
+        assert_eq!(&header_name("hello"), b"hello               ");
+        assert_eq!(
+            &header_name("abcdefghijklmnopqrstuvwxyz0123"),
+            b"abcdefghijklmnopqrst"
+        );
+        assert_eq!(
+            &header_name("nineteen characters\u{e9}"),
+            b"nineteen characters "
+        );
+    }
+
+    #[test]
+    fn test_header_options() {
+        let mut options = RomOptions::new("header", IPL3::Cic6102([0; IPL_SIZE]));
+        let header = N64Header::new(0x8000_0400, &[], &[], &options).to_vec();
+        assert_eq!(header[..8], [128, 55, 18, 64, 0, 0, 0, 15]);
+
+        options.header = HeaderOptions {
+            device_latency: 64,
+            device_rw_pulse_width: 18,
+            device_page_size: 7,
+            device_rw_release_duration: 2,
+            clock_rate: 0x1234,
+        };
+        let header = N64Header::new(0x8000_0400, &[], &[], &options).to_vec();
+        assert_eq!(header[..8], [64, 18, 7, 2, 0, 0, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let mut options = RomOptions::new("round trip", IPL3::Cic6105([0; IPL_SIZE]));
+        options.region = b'P';
+        options.cart_id = *b"RT";
+        let header = N64Header::new(0x8000_0400, &[1, 2, 3, 4], &[], &options);
+        let bytes = header.to_vec();
+
+        assert_eq!(bytes.len(), HEADER_SIZE);
+        assert_eq!(N64Header::from_bytes(&bytes).unwrap(), header);
+        assert_eq!(N64Header::from_bytes(&bytes).unwrap().to_vec(), bytes);
+        assert!(N64Header::from_bytes(&bytes[..HEADER_SIZE - 1]).is_err());
+    }
+}
@@ -1,15 +1,20 @@
-use crate::header::HEADER_SIZE;
+use crate::header::{N64Header, HEADER_SIZE};
 use crc32fast::Hasher;
+use flate2::read::{DeflateDecoder, GzDecoder};
+use std::cmp;
 use std::convert::TryInto;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read, Seek, SeekFrom};
 use std::num::Wrapping;
 use std::path::Path;
 use thiserror::Error;
 
-pub(crate) const IPL_SIZE: usize = 0x0fc0;
-pub(crate) const PROGRAM_SIZE: usize = 1024 * 1024;
+pub const IPL_SIZE: usize = 0x0fc0;
+pub const PROGRAM_SIZE: usize = 1024 * 1024;
+
+/// Default fill byte for padding, in the ROM image and the checksum window
+pub const PAD_BYTE: u8 = 0xFF;
 
 #[derive(Debug, Error)]
 pub enum IPL3Error {
@@ -21,28 +26,90 @@ pub enum IPL3Error {
 }
 
 /// IPL3 definitions.
-pub(crate) enum IPL3 {
+#[derive(Clone)]
+pub enum IPL3 {
     Cic6101([u8; IPL_SIZE]),
     Cic6102([u8; IPL_SIZE]),
     Cic6103([u8; IPL_SIZE]),
     Cic6105([u8; IPL_SIZE]),
     Cic6106([u8; IPL_SIZE]),
     Cic7102([u8; IPL_SIZE]),
+    Cic8303([u8; IPL_SIZE]),
     Unknown([u8; IPL_SIZE]),
 }
 
+/// Known CICs, without the IPL bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IPL3Kind {
+    Cic6101,
+    Cic6102,
+    Cic6103,
+    Cic6105,
+    Cic6106,
+    Cic7102,
+    Cic8303,
+}
+
+impl IPL3Kind {
+    /// Every known CIC.
+    pub const ALL: [IPL3Kind; 7] = [
+        IPL3Kind::Cic6101,
+        IPL3Kind::Cic6102,
+        IPL3Kind::Cic6103,
+        IPL3Kind::Cic6105,
+        IPL3Kind::Cic6106,
+        IPL3Kind::Cic7102,
+        IPL3Kind::Cic8303,
+    ];
+
+    /// Look up a CIC by number, e.g. `6102`.
+    pub fn from_cic_number(n: u16) -> Option<IPL3Kind> {
+        Self::ALL.into_iter().find(|kind| kind.cic_number() == n)
+    }
+
+    /// The full CIC name, e.g. `CIC-NUS-6102`.
+    pub fn name(self) -> &'static str {
+        match self {
+            IPL3Kind::Cic6101 => "CIC-NUS-6101",
+            IPL3Kind::Cic6102 => "CIC-NUS-6102",
+            IPL3Kind::Cic6103 => "CIC-NUS-6103",
+            IPL3Kind::Cic6105 => "CIC-NUS-6105",
+            IPL3Kind::Cic6106 => "CIC-NUS-6106",
+            IPL3Kind::Cic7102 => "CIC-NUS-7102",
+            IPL3Kind::Cic8303 => "CIC-NUS-8303",
+        }
+    }
+
+    /// The CIC number, e.g. `6102`.
+    pub fn cic_number(self) -> u16 {
+        match self {
+            IPL3Kind::Cic6101 => 6101,
+            IPL3Kind::Cic6102 => 6102,
+            IPL3Kind::Cic6103 => 6103,
+            IPL3Kind::Cic6105 => 6105,
+            IPL3Kind::Cic6106 => 6106,
+            IPL3Kind::Cic7102 => 7102,
+            IPL3Kind::Cic8303 => 8303,
+        }
+    }
+
+    /// Pair this CIC with IPL bytes.
+    pub fn with_ipl(self, ipl: [u8; IPL_SIZE]) -> IPL3 {
+        match self {
+            IPL3Kind::Cic6101 => IPL3::Cic6101(ipl),
+            IPL3Kind::Cic6102 => IPL3::Cic6102(ipl),
+            IPL3Kind::Cic6103 => IPL3::Cic6103(ipl),
+            IPL3Kind::Cic6105 => IPL3::Cic6105(ipl),
+            IPL3Kind::Cic6106 => IPL3::Cic6106(ipl),
+            IPL3Kind::Cic7102 => IPL3::Cic7102(ipl),
+            IPL3Kind::Cic8303 => IPL3::Cic8303(ipl),
+        }
+    }
+}
+
 impl fmt::Display for IPL3 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self {
-            IPL3::Cic6101(_) => "CIC-NUS-6101",
-            IPL3::Cic6102(_) => "CIC-NUS-6102",
-            IPL3::Cic6103(_) => "CIC-NUS-6103",
-            IPL3::Cic6105(_) => "CIC-NUS-6105",
-            IPL3::Cic6106(_) => "CIC-NUS-6106",
-            IPL3::Cic7102(_) => "CIC-NUS-7102",
-            IPL3::Unknown(_) => "Unknown",
-        };
-        write!(f, "{}", s)
+        write!(f, "{}", self.name())
     }
 }
 
@@ -53,44 +120,103 @@ impl fmt::Debug for IPL3 {
 }
 
 impl IPL3 {
-    pub(crate) fn read(path: impl AsRef<Path>) -> Result<IPL3, IPL3Error> {
-        let mut f = File::open(path)?;
+    /// Read an IPL3 from a raw bootcode file.
+    ///
+    /// The file may contain exactly `IPL_SIZE` bytes, the bootcode with the
+    /// `HEADER_SIZE` ROM header still attached, or the bootcode followed by
+    /// zero padding.
+    pub fn read(path: impl AsRef<Path>) -> Result<IPL3, IPL3Error> {
+        let data = fs::read(path)?;
 
+        Self::from_bootcode(&data)
+    }
+
+    fn from_bootcode(data: &[u8]) -> Result<IPL3, IPL3Error> {
         // Check the file size
-        let metadata = f.metadata()?;
-        let len = metadata.len();
-        if len as usize != IPL_SIZE {
+        if data.len() < IPL_SIZE {
             return Err(IPL3Error::IPL3ReadError(format!(
-                "Expected file size {}, found {}",
-                IPL_SIZE, len
+                "Expected file size of at least {}, found {}",
+                IPL_SIZE,
+                data.len()
             )));
         }
 
-        // Read file contents
-        let mut ipl = [0; IPL_SIZE];
-        f.read_exact(&mut ipl)?;
+        let leading = Self::check(data[..IPL_SIZE].try_into().unwrap());
 
-        Ok(Self::check(ipl))
-    }
-
-    pub(crate) fn read_from_rom(path: impl AsRef<Path>) -> Result<IPL3, IPL3Error> {
-        let mut f = File::open(&path)?;
-        f.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+        // A bootcode with the header attached has the same size as one padded
+        // to 4 KiB. Skip the header unless only the leading bytes are recognized.
+        if data.len() == HEADER_SIZE + IPL_SIZE {
+            let headerless = Self::check(data[HEADER_SIZE..].try_into().unwrap());
 
-        let mut ipl = [0; IPL_SIZE];
+            if !matches!(headerless, IPL3::Unknown(_)) || matches!(leading, IPL3::Unknown(_)) {
+                return Ok(headerless);
+            }
+        }
 
-        if f.read(&mut ipl)? != IPL_SIZE {
+        // Anything else past the bootcode is likely not a bootcode file at all
+        if data[IPL_SIZE..].iter().any(|&byte| byte != 0) {
             return Err(IPL3Error::IPL3ReadError(format!(
+                "Expected only zero padding after the first {} bytes",
+                IPL_SIZE
+            )));
+        }
+
+        Ok(leading)
+    }
+
+    /// Read the IPL3 of an existing ROM, which may be compressed with gzip or
+    /// stored in a zip archive as a `.z64`, `.n64` or `.v64` file.
+    ///
+    /// An unrecognized bootcode takes the checksum algorithm of the known CIC
+    /// whose CRCs match those in the ROM header, when there is one. The IPL
+    /// bytes are copied from the ROM either way.
+    pub fn read_from_rom(path: impl AsRef<Path>) -> Result<IPL3, IPL3Error> {
+        let mut ipl = Vec::with_capacity(IPL_SIZE);
+        open_rom_at(path.as_ref(), HEADER_SIZE)?
+            .take(IPL_SIZE as u64)
+            .read_to_end(&mut ipl)?;
+
+        let ipl: [u8; IPL_SIZE] = ipl.try_into().map_err(|_| {
+            IPL3Error::IPL3ReadError(format!(
                 "Couldn't read all {} IPL3 bytes from ROM \"{}\".",
                 IPL_SIZE,
                 path.as_ref().display()
-            )));
+            ))
+        })?;
+
+        let ipl3 = Self::check(ipl);
+        if ipl3.kind().is_some() {
+            return Ok(ipl3);
         }
 
-        Ok(Self::check(ipl))
+        let mut rom = Vec::new();
+        open_rom_at(path.as_ref(), 0)?.read_to_end(&mut rom)?;
+
+        Ok(Self::from_rom_crcs(ipl, &rom).unwrap_or(ipl3))
     }
 
-    fn check(ipl: [u8; IPL_SIZE]) -> IPL3 {
+    /// Pair unrecognized IPL bytes with a known CIC whose checksum algorithm
+    /// reproduces the CRCs in the header of `rom`. The 6101, 6102 and 7102 share
+    /// their seed, so the common 6102 is preferred when several CICs match.
+    fn from_rom_crcs(ipl: [u8; IPL_SIZE], rom: &[u8]) -> Option<IPL3> {
+        let stored_crcs = N64Header::from_bytes(rom).ok()?.crcs();
+        let program = rom.get(HEADER_SIZE + IPL_SIZE..)?;
+
+        let matches: Vec<_> = Self::known(ipl)
+            .into_iter()
+            .filter(|candidate| {
+                candidate.compute_crcs_reader(program, program.len()).ok() == Some(stored_crcs)
+            })
+            .collect();
+
+        let preferred = matches
+            .iter()
+            .position(|candidate| candidate.kind() == Some(IPL3Kind::Cic6102))
+            .unwrap_or(0);
+        matches.into_iter().nth(preferred)
+    }
+
+    pub(crate) fn check(ipl: [u8; IPL_SIZE]) -> IPL3 {
         // Check for known IPLs
         let mut hasher = Hasher::new();
         hasher.update(&ipl);
@@ -101,11 +227,45 @@ impl IPL3 {
             0x98bc_2c86 => IPL3::Cic6105(ipl),
             0xacc8_580a => IPL3::Cic6106(ipl),
             0x009e_9ea3 => IPL3::Cic7102(ipl),
+            0x0e01_8159 => IPL3::Cic8303(ipl),
             _ => IPL3::Unknown(ipl),
         }
     }
 
-    pub(crate) fn get_ipl(&self) -> &[u8; IPL_SIZE] {
+    /// Every known CIC, each paired with the given IPL bytes. Used to try the
+    /// checksum algorithm of each CIC when the bootcode is not recognized.
+    pub fn known(ipl: [u8; IPL_SIZE]) -> Vec<IPL3> {
+        IPL3Kind::ALL
+            .into_iter()
+            .map(|kind| kind.with_ipl(ipl))
+            .collect()
+    }
+
+    /// The CIC this IPL3 was recognized as, or `None` when unknown.
+    pub fn kind(&self) -> Option<IPL3Kind> {
+        match self {
+            IPL3::Cic6101(_) => Some(IPL3Kind::Cic6101),
+            IPL3::Cic6102(_) => Some(IPL3Kind::Cic6102),
+            IPL3::Cic6103(_) => Some(IPL3Kind::Cic6103),
+            IPL3::Cic6105(_) => Some(IPL3Kind::Cic6105),
+            IPL3::Cic6106(_) => Some(IPL3Kind::Cic6106),
+            IPL3::Cic7102(_) => Some(IPL3Kind::Cic7102),
+            IPL3::Cic8303(_) => Some(IPL3Kind::Cic8303),
+            IPL3::Unknown(_) => None,
+        }
+    }
+
+    /// The full CIC name, e.g. `CIC-NUS-6102`, or `Unknown`.
+    pub fn name(&self) -> &'static str {
+        self.kind().map_or("Unknown", IPL3Kind::name)
+    }
+
+    /// The CIC number, e.g. `6102`, or `0` when unknown.
+    pub fn cic_number(&self) -> u16 {
+        self.kind().map_or(0, IPL3Kind::cic_number)
+    }
+
+    pub fn get_ipl(&self) -> &[u8; IPL_SIZE] {
         match self {
             IPL3::Cic6101(bin) => bin,
             IPL3::Cic6102(bin) => bin,
@@ -113,89 +273,209 @@ impl IPL3 {
             IPL3::Cic6105(bin) => bin,
             IPL3::Cic6106(bin) => bin,
             IPL3::Cic7102(bin) => bin,
+            IPL3::Cic8303(bin) => bin,
             IPL3::Unknown(bin) => bin,
         }
     }
 
-    /// Compute N64 checksums for a program.
+    /// Check whether this is the given CIC, named by number (`6102`) or in full
+    /// (`CIC-NUS-6102`). An unknown IPL3 never matches.
+    pub fn is_cic(&self, cic: &str) -> bool {
+        let kind = match self.kind() {
+            Some(kind) => kind,
+            None => return false,
+        };
+
+        kind.name().eq_ignore_ascii_case(cic) || cic.parse::<u16>().ok() == Some(kind.cic_number())
+    }
+
+    /// The region code conventionally paired with this CIC, if known.
+    pub fn region(&self) -> Option<u8> {
+        match self {
+            IPL3::Cic6101(_)
+            | IPL3::Cic6102(_)
+            | IPL3::Cic6103(_)
+            | IPL3::Cic6105(_)
+            | IPL3::Cic6106(_) => Some(b'E'), // USA/English (NTSC)
+            IPL3::Cic7102(_) => Some(b'P'), // Europe (PAL)
+            IPL3::Cic8303(_) => Some(b'J'), // Japan (64DD)
+            IPL3::Unknown(_) => None,
+        }
+    }
+
+    /// Compute N64 checksums for a program, padded with [`PAD_BYTE`].
     ///
     /// Panics if `program` or `fs` lengths are not evenly divisible by `size_of::<u32>`.
-    pub(crate) fn compute_crcs(&self, program: &[u8], fs: &[u8]) -> (u32, u32) {
+    pub fn compute_crcs(&self, program: &[u8], fs: &[u8]) -> (u32, u32) {
+        self.compute_crcs_padded(program, fs, PAD_BYTE)
+    }
+
+    /// Compute N64 checksums for a program, padded with `pad_byte`.
+    ///
+    /// The checksum window covers the ROM image as written: a short program is
+    /// padded up to `PROGRAM_SIZE` before the file system, so only the part of
+    /// `fs` following a program larger than the window is ever left out.
+    ///
+    /// Panics if `program` or `fs` lengths are not evenly divisible by `size_of::<u32>`.
+    pub fn compute_crcs_padded(&self, program: &[u8], fs: &[u8], pad_byte: u8) -> (u32, u32) {
         let word = std::mem::size_of::<u32>();
         assert!(program.len() % word == 0);
         assert!(fs.len() % word == 0);
 
-        let padding = [0; 4];
+        let padding = [pad_byte; 4];
+        let padding_words = PROGRAM_SIZE.saturating_sub(program.len()) / word;
         let program = program
-            .chunks(4)
-            .chain(fs.chunks(4))
-            .chain(std::iter::repeat(&padding[..]))
+            .chunks(word)
+            .chain(std::iter::repeat(&padding[..]).take(padding_words))
+            .chain(fs.chunks(word))
             .take(PROGRAM_SIZE / word);
 
+        // Iterate 1-word at a time
+        let mut checksum = Checksum::new(self);
+        for chunk in program {
+            checksum.update(u32::from_be_bytes(chunk.try_into().unwrap()));
+        }
+
+        checksum.finish()
+    }
+
+    /// Compute N64 checksums for a program read from `reader`.
+    ///
+    /// At most `program_len` bytes are read, in bounded chunks, so the program
+    /// never needs to be fully buffered. A short program is padded with
+    /// [`PAD_BYTE`], matching the padding `compute_crcs` applies.
+    pub fn compute_crcs_reader(
+        &self,
+        mut reader: impl Read,
+        program_len: usize,
+    ) -> io::Result<(u32, u32)> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+
+        let word = std::mem::size_of::<u32>();
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut remaining = cmp::min(program_len, PROGRAM_SIZE);
+        let mut checksum = Checksum::new(self);
+
+        while remaining > 0 {
+            let chunk = &mut buffer[..cmp::min(remaining, CHUNK_SIZE)];
+            reader.read_exact(chunk)?;
+            remaining -= chunk.len();
+
+            for bytes in chunk.chunks(word) {
+                let mut current = [PAD_BYTE; 4];
+                current[..bytes.len()].copy_from_slice(bytes);
+                checksum.update(u32::from_be_bytes(current));
+            }
+        }
+
+        // Pad the remainder of the checksum window
+        let padding = u32::from_be_bytes([PAD_BYTE; 4]);
+        while checksum.words < PROGRAM_SIZE / word {
+            checksum.update(padding);
+        }
+
+        Ok(checksum.finish())
+    }
+
+    /// Offset the entry point for the current IPL3
+    pub fn offset(&self, entry_point: u32) -> u32 {
+        entry_point + self.entry_offset()
+    }
+
+    /// The amount added to the entry point by [`IPL3::offset`]
+    pub fn entry_offset(&self) -> u32 {
+        match self {
+            IPL3::Cic6103(_) => 0x0010_0000,
+            IPL3::Cic6106(_) => 0x0020_0000,
+            _ => 0,
+        }
+    }
+}
+
+/// Running state of the N64 checksum algorithm, advanced one word at a time.
+struct Checksum<'a> {
+    ipl3: &'a IPL3,
+    words: usize,
+
+    // Six accumulators
+    acc1: Wrapping<u32>,
+    acc2: Wrapping<u32>,
+    acc3: Wrapping<u32>,
+    acc4: Wrapping<u32>,
+    acc5: Wrapping<u32>,
+    acc6: Wrapping<u32>,
+}
+
+impl<'a> Checksum<'a> {
+    fn new(ipl3: &'a IPL3) -> Self {
         // Initial checksum value
-        let checksum = match self {
+        let checksum = Wrapping(match ipl3 {
             IPL3::Cic6103(_) => 0xa388_6759,
             IPL3::Cic6105(_) => 0xdf26_f436,
             IPL3::Cic6106(_) => 0x1fea_617a,
+            IPL3::Cic8303(_) => 0x9570_5632,
             _ => 0xf8ca_4ddc,
-        };
-
-        // NUS-IPL3-6105 has a special 64-word table hidden in the IPL
-        let mut ipl = self.get_ipl().chunks(4).skip(452).take(64).cycle();
-
-        // Six accumulators
-        let mut acc1 = Wrapping(checksum);
-        let mut acc2 = Wrapping(checksum);
-        let mut acc3 = Wrapping(checksum);
-        let mut acc4 = Wrapping(checksum);
-        let mut acc5 = Wrapping(checksum);
-        let mut acc6 = Wrapping(checksum);
+        });
+
+        Self {
+            ipl3,
+            words: 0,
+            acc1: checksum,
+            acc2: checksum,
+            acc3: checksum,
+            acc4: checksum,
+            acc5: checksum,
+            acc6: checksum,
+        }
+    }
 
-        // Some temporary state
-        let mut current;
-        let mut rotated;
+    fn update(&mut self, current: u32) {
+        // Fetch the current word and rotate it by itself
+        let current = Wrapping(current);
+        let rotated = Wrapping(current.0.rotate_left((current & Wrapping(0x1f)).0));
 
-        // Iterate 1-word at a time
-        for chunk in program {
-            // Fetch the current word and rotate it by itself
-            current = Wrapping(u32::from_be_bytes(chunk.try_into().unwrap()));
-            rotated = Wrapping(current.0.rotate_left((current & Wrapping(0x1f)).0));
+        // Advance accumulator 1
+        self.acc1 += current;
 
-            // Advance accumulator 1
-            acc1 += current;
+        // Advance accumulator 2
+        if self.acc1 < current {
+            self.acc2 += Wrapping(1);
+        }
 
-            // Advance accumulator 2
-            if acc1 < current {
-                acc2 += Wrapping(1);
-            }
+        // Advance accumulator 3
+        self.acc3 ^= current;
 
-            // Advance accumulator 3
-            acc3 ^= current;
+        // Advance accumulator 4
+        self.acc4 += rotated;
 
-            // Advance accumulator 4
-            acc4 += rotated;
+        // Advance accumulator 5
+        if self.acc5 > current {
+            self.acc5 ^= rotated;
+        } else {
+            self.acc5 ^= self.acc1 ^ current;
+        }
 
-            // Advance accumulator 5
-            if acc5 > current {
-                acc5 ^= rotated;
-            } else {
-                acc5 ^= acc1 ^ current;
+        // Advance accumulator 6
+        match self.ipl3 {
+            IPL3::Cic6105(ipl) => {
+                // NUS-IPL3-6105 has a special 64-word table hidden in the IPL
+                let offset = (452 + self.words % 64) * 4;
+                let current_ipl = ipl[offset..offset + 4].try_into().unwrap();
+                self.acc6 += current ^ Wrapping(u32::from_be_bytes(current_ipl));
             }
-
-            // Advance accumulator 6
-            match self {
-                IPL3::Cic6105(_) => {
-                    let current_ipl = ipl.next().unwrap();
-                    let current_ipl = Wrapping(u32::from_be_bytes(current_ipl.try_into().unwrap()));
-                    acc6 += current ^ current_ipl;
-                }
-                _ => {
-                    acc6 += current ^ acc4;
-                }
+            _ => {
+                self.acc6 += current ^ self.acc4;
             }
         }
 
-        let (crc1, crc2) = match self {
+        self.words += 1;
+    }
+
+    fn finish(self) -> (u32, u32) {
+        let (acc1, acc2, acc3) = (self.acc1, self.acc2, self.acc3);
+        let (acc4, acc5, acc6) = (self.acc4, self.acc5, self.acc6);
+
+        let (crc1, crc2) = match self.ipl3 {
             IPL3::Cic6103(_) => ((acc1 ^ acc2) + acc3, (acc4 ^ acc5) + acc6),
             IPL3::Cic6106(_) => (acc1 * acc2 + acc3, acc4 * acc5 + acc6),
             _ => (acc1 ^ acc2 ^ acc3, acc4 ^ acc5 ^ acc6),
@@ -203,21 +483,129 @@ impl IPL3 {
 
         (crc1.0, crc2.0)
     }
+}
 
-    /// Offset the entry point for the current IPL3
-    pub(crate) fn offset(&self, entry_point: u32) -> u32 {
-        entry_point
-            + match self {
-                IPL3::Cic6103(_) => 0x0010_0000,
-                IPL3::Cic6106(_) => 0x0020_0000,
-                _ => 0,
-            }
+/// Open a ROM file for reading from `offset`. Raw ROMs are seeked directly,
+/// gzip files and the ROM entry of a zip archive are decompressed on the fly
+/// and the bytes before `offset` are discarded.
+fn open_rom_at(path: &Path, offset: usize) -> io::Result<Box<dyn Read>> {
+    let mut file = File::open(path)?;
+    let mut magic = Vec::with_capacity(4);
+    file.by_ref().take(4).read_to_end(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut reader: Box<dyn Read> = match magic.as_slice() {
+        [0x1f, 0x8b, ..] => Box::new(GzDecoder::new(file)),
+        [b'P', b'K', 3, 4] => open_zip_entry(file)?,
+        _ => {
+            file.seek(SeekFrom::Start(offset as u64))?;
+            return Ok(Box::new(file));
+        }
+    };
+
+    io::copy(&mut reader.by_ref().take(offset as u64), &mut io::sink())?;
+
+    Ok(reader)
+}
+
+/// File name extensions of the ROM entries looked for in a zip archive
+const ZIP_ROM_EXTENSIONS: [&str; 3] = [".z64", ".n64", ".v64"];
+
+/// Read the first ROM entry in a zip archive. Entries are found through the
+/// central directory, which holds the sizes even when the local file headers
+/// defer them to a data descriptor.
+fn open_zip_entry(mut file: File) -> io::Result<Box<dyn Read>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let half = |bytes: &[u8], offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let word = |bytes: &[u8], offset: usize| {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    };
+
+    // The end of central directory record is followed by a comment of up to 64 KiB
+    let len = file.seek(SeekFrom::End(0))?;
+    let tail_len = cmp::min(len, 22 + 0xffff);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = Vec::new();
+    file.by_ref().read_to_end(&mut tail)?;
+    let eocd = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| tail[i..i + 4] == [b'P', b'K', 5, 6])
+        .map(|i| &tail[i..])
+        .ok_or_else(|| invalid("Missing zip central directory".to_owned()))?;
+
+    let entries = half(eocd, 10);
+    let directory_size = word(eocd, 12);
+    let directory_offset = word(eocd, 16);
+    if entries == 0xffff || directory_size == 0xffff_ffff || directory_offset == 0xffff_ffff {
+        return Err(invalid("Zip64 archives are not supported".to_owned()));
     }
+
+    let mut directory = vec![0; directory_size as usize];
+    file.seek(SeekFrom::Start(u64::from(directory_offset)))?;
+    file.read_exact(&mut directory)?;
+
+    let mut entry = &directory[..];
+    for _ in 0..entries {
+        if entry.len() < 46 || entry[..4] != [b'P', b'K', 1, 2] {
+            return Err(invalid("Corrupt zip central directory".to_owned()));
+        }
+
+        let name_len = usize::from(half(entry, 28));
+        let entry_len = 46 + name_len + usize::from(half(entry, 30)) + usize::from(half(entry, 32));
+        let name = String::from_utf8_lossy(entry.get(46..46 + name_len).unwrap_or_default());
+        let is_rom = ZIP_ROM_EXTENSIONS
+            .iter()
+            .any(|extension| name.to_ascii_lowercase().ends_with(extension));
+        if !is_rom {
+            entry = entry.get(entry_len..).unwrap_or_default();
+            continue;
+        }
+
+        let flags = half(entry, 8);
+        let method = half(entry, 10);
+        let size = word(entry, 20);
+        let offset = word(entry, 42);
+        if flags & 1 != 0 {
+            return Err(invalid(format!(
+                "Encrypted zip entry \"{}\" is not supported",
+                name
+            )));
+        }
+        if size == 0xffff_ffff || offset == 0xffff_ffff {
+            return Err(invalid(format!(
+                "Zip64 entry \"{}\" is not supported",
+                name
+            )));
+        }
+
+        // Skip the local file header, its name and extra field may differ
+        let mut header = [0; 30];
+        file.seek(SeekFrom::Start(u64::from(offset)))?;
+        file.read_exact(&mut header)?;
+        let extra = i64::from(half(&header, 26)) + i64::from(half(&header, 28));
+        file.seek(SeekFrom::Current(extra))?;
+
+        let data = file.take(u64::from(size));
+        return match method {
+            0 => Ok(Box::new(data)),
+            8 => Ok(Box::new(DeflateDecoder::new(data))),
+            _ => Err(invalid(format!(
+                "Unsupported zip compression method {}",
+                method
+            ))),
+        };
+    }
+
+    Err(invalid(format!(
+        "No ROM ({}) in zip archive",
+        ZIP_ROM_EXTENSIONS.join(", ")
+    )))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::scratch_dir;
 
     #[test]
     fn crc_ipl3_6101() {
@@ -285,6 +673,275 @@ mod tests {
         assert_eq!(crc2, 0xb2de_a121);
     }
 
+    #[test]
+    fn crc_ipl3_8303() {
+        let ipl3 = IPL3::Cic8303([0; IPL_SIZE]);
+        let program: Vec<u8> = (0..PROGRAM_SIZE).map(|i| i as u8).collect();
+
+        let (crc1, crc2) = ipl3.compute_crcs(&program, &[]);
+
+        assert_eq!(crc1, 0x9776_5030);
+        assert_eq!(crc2, 0x27fb_ba03);
+    }
+
+    #[test]
+    fn crc_short_program_padding() {
+        let ipl3 = IPL3::Cic6102([0; IPL_SIZE]);
+        let program = [1, 2, 3, 4];
+        let fs = [5, 6, 7, 8];
+
+        let mut padded = program.to_vec();
+        padded.resize(PROGRAM_SIZE, PAD_BYTE);
+        assert_eq!(
+            ipl3.compute_crcs(&program, &fs),
+            ipl3.compute_crcs(&padded, &[])
+        );
+
+        let mut zeros = program.to_vec();
+        zeros.resize(PROGRAM_SIZE, 0);
+        assert_eq!(
+            ipl3.compute_crcs_padded(&program, &fs, 0),
+            ipl3.compute_crcs(&zeros, &[])
+        );
+        assert_ne!(
+            ipl3.compute_crcs(&program, &[]),
+            ipl3.compute_crcs(&zeros, &[])
+        );
+    }
+
+    #[test]
+    fn crc_reader_matches_slice() {
+        let ipl3 = IPL3::Cic6105([0x5a; IPL_SIZE]);
+        let program: Vec<u8> = (0..PROGRAM_SIZE / 2).map(|i| (i * 7) as u8).collect();
+
+        let crcs = ipl3
+            .compute_crcs_reader(&program[..], program.len())
+            .unwrap();
+
+        assert_eq!(crcs, ipl3.compute_crcs(&program, &[]));
+    }
+
+    #[test]
+    fn crc_reader_unaligned_length() {
+        let ipl3 = IPL3::Cic6102([0; IPL_SIZE]);
+        let program: Vec<u8> = (0..1001).map(|i| i as u8).collect();
+        let mut padded = program.clone();
+        padded.resize(1004, PAD_BYTE);
+
+        let crcs = ipl3
+            .compute_crcs_reader(&program[..], program.len())
+            .unwrap();
+
+        assert_eq!(crcs, ipl3.compute_crcs(&padded, &[]));
+    }
+
+    #[test]
+    fn read_bootcode_exact() {
+        let bootcode: Vec<u8> = (0..IPL_SIZE).map(|i| i as u8).collect();
+
+        let ipl3 = IPL3::from_bootcode(&bootcode).unwrap();
+
+        assert_eq!(&ipl3.get_ipl()[..], &bootcode[..]);
+    }
+
+    #[test]
+    fn read_bootcode_with_header() {
+        let bootcode: Vec<u8> = (0..IPL_SIZE).map(|i| i as u8).collect();
+        let mut data = vec![0x80; HEADER_SIZE];
+        data.extend_from_slice(&bootcode);
+
+        let ipl3 = IPL3::from_bootcode(&data).unwrap();
+
+        assert_eq!(&ipl3.get_ipl()[..], &bootcode[..]);
+    }
+
+    #[test]
+    fn read_bootcode_with_padding() {
+        let bootcode: Vec<u8> = (0..IPL_SIZE).map(|i| i as u8).collect();
+        let mut data = bootcode.clone();
+        data.resize(8 * 1024, 0);
+
+        let ipl3 = IPL3::from_bootcode(&data).unwrap();
+
+        assert_eq!(&ipl3.get_ipl()[..], &bootcode[..]);
+    }
+
+    #[test]
+    fn read_bootcode_with_trailing_data() {
+        let mut data: Vec<u8> = (0..IPL_SIZE).map(|i| i as u8).collect();
+        data.resize(8 * 1024, 0);
+        data[6 * 1024] = 0x42;
+
+        assert!(IPL3::from_bootcode(&data).is_err());
+    }
+
+    #[test]
+    fn read_bootcode_too_short() {
+        assert!(IPL3::from_bootcode(&[0; IPL_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn is_cic_ipl3() {
+        let ipl3 = IPL3::Cic6102([0; IPL_SIZE]);
+        assert!(ipl3.is_cic("6102"));
+        assert!(ipl3.is_cic("CIC-NUS-6102"));
+        assert!(ipl3.is_cic("cic-nus-6102"));
+        assert!(!ipl3.is_cic("6105"));
+        assert!(!IPL3::Unknown([0; IPL_SIZE]).is_cic("Unknown"));
+    }
+
+    #[test]
+    fn read_unknown_from_rom() {
+        let ipl = [0x5a; IPL_SIZE];
+        let options = crate::RomOptions::new("seed", IPL3::Cic6105(ipl));
+        let rom = crate::build_rom(0x8000_0400, vec![0x42; 64], None, options).unwrap();
+
+        let dir = scratch_dir("unknown-seed");
+        let path = dir.join("rom.n64");
+        fs::write(&path, &rom).unwrap();
+        let ipl3 = IPL3::read_from_rom(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(ipl3.kind(), Some(IPL3Kind::Cic6105));
+        assert_eq!(ipl3.get_ipl(), &ipl);
+        assert!(IPL3::from_rom_crcs(ipl, &rom[..HEADER_SIZE]).is_none());
+    }
+
+    #[test]
+    fn read_unknown_from_rom_prefers_6102() {
+        let ipl = [0x5a; IPL_SIZE];
+        let options = crate::RomOptions::new("shared", IPL3::Cic6102(ipl));
+        let rom = crate::build_rom(0x8000_0400, vec![0x42; 64], None, options).unwrap();
+
+        // 6101, 6102 and 7102 all reproduce these CRCs
+        let ipl3 = IPL3::from_rom_crcs(ipl, &rom).unwrap();
+        assert_eq!(ipl3.kind(), Some(IPL3Kind::Cic6102));
+    }
+
+    #[test]
+    fn read_compressed_rom() {
+        use flate2::write::{DeflateEncoder, GzEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        let options = crate::RomOptions::new("compressed", IPL3::Cic6102([0x5a; IPL_SIZE]));
+        let rom = crate::build_rom(0x8000_0400, vec![0x42; 64], None, options).unwrap();
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&rom).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(&rom).unwrap();
+        let deflate = deflate.finish().unwrap();
+        // The ROM is not the first entry, and its sizes are in a data descriptor
+        let zip = zip_archive(&[
+            ("README.txt", 0, 0, &b"readme"[..], 6),
+            ("rom.z64", 1 << 3, 8, &deflate[..], rom.len()),
+        ]);
+
+        let dir = scratch_dir("compressed");
+        for (extension, contents) in [("z64", &rom), ("z64.gz", &gzip), ("zip", &zip)] {
+            let path = dir.join(format!("rom.{}", extension));
+            fs::write(&path, contents).unwrap();
+            let ipl3 = IPL3::read_from_rom(&path).unwrap();
+
+            assert_eq!(ipl3.get_ipl(), &[0x5a; IPL_SIZE], "{}", extension);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_unsupported_zip() {
+        let ipl = [0; HEADER_SIZE + IPL_SIZE];
+        for (name, zip) in [
+            (
+                "encrypted",
+                zip_archive(&[("rom.n64", 1, 0, &ipl[..], ipl.len())]),
+            ),
+            (
+                "no-rom",
+                zip_archive(&[("rom.bin", 0, 0, &ipl[..], ipl.len())]),
+            ),
+        ] {
+            let dir = scratch_dir(name);
+            let path = dir.join("rom.zip");
+            fs::write(&path, zip).unwrap();
+            let result = IPL3::read_from_rom(&path);
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert!(result.is_err(), "{}", name);
+        }
+    }
+
+    /// Build a zip archive of `(name, flags, method, data, size)` entries. Local
+    /// file headers leave the sizes to the central directory.
+    fn zip_archive(entries: &[(&str, u16, u16, &[u8], usize)]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut directory = Vec::new();
+        for &(name, flags, method, data, size) in entries {
+            let offset = zip.len() as u32;
+            zip.extend_from_slice(&[b'P', b'K', 3, 4, 20, 0]);
+            zip.extend_from_slice(&flags.to_le_bytes());
+            zip.extend_from_slice(&method.to_le_bytes());
+            zip.extend_from_slice(&[0; 16]); // Time, date, CRC-32, sizes
+            zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            zip.extend_from_slice(&[0, 0]);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(data);
+
+            directory.extend_from_slice(&[b'P', b'K', 1, 2, 20, 0, 20, 0]);
+            directory.extend_from_slice(&flags.to_le_bytes());
+            directory.extend_from_slice(&method.to_le_bytes());
+            directory.extend_from_slice(&[0; 8]); // Time, date, CRC-32
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            directory.extend_from_slice(&(size as u32).to_le_bytes());
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 12]); // Extra, comment, disk, attributes
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+
+        let directory_offset = zip.len() as u32;
+        zip.extend_from_slice(&directory);
+        zip.extend_from_slice(&[b'P', b'K', 5, 6, 0, 0, 0, 0]);
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&directory_offset.to_le_bytes());
+        zip.extend_from_slice(&[0, 0]);
+
+        zip
+    }
+
+    #[test]
+    fn cic_metadata() {
+        let ipl = [0; IPL_SIZE];
+        assert_eq!(IPL3::Cic7102(ipl).name(), "CIC-NUS-7102");
+        assert_eq!(IPL3::Cic7102(ipl).cic_number(), 7102);
+        assert_eq!(IPL3::Unknown(ipl).name(), "Unknown");
+        assert_eq!(IPL3::Unknown(ipl).cic_number(), 0);
+
+        for kind in IPL3Kind::ALL {
+            assert_eq!(IPL3Kind::from_cic_number(kind.cic_number()), Some(kind));
+            assert_eq!(kind.with_ipl(ipl).kind(), Some(kind));
+        }
+        assert_eq!(IPL3Kind::from_cic_number(6104), None);
+    }
+
+    #[test]
+    fn region_ipl3() {
+        let ipl = [0; IPL_SIZE];
+        assert_eq!(IPL3::Cic6101(ipl).region(), Some(b'E'));
+        assert_eq!(IPL3::Cic6102(ipl).region(), Some(b'E'));
+        assert_eq!(IPL3::Cic6103(ipl).region(), Some(b'E'));
+        assert_eq!(IPL3::Cic6105(ipl).region(), Some(b'E'));
+        assert_eq!(IPL3::Cic6106(ipl).region(), Some(b'E'));
+        assert_eq!(IPL3::Cic7102(ipl).region(), Some(b'P'));
+        assert_eq!(IPL3::Cic8303(ipl).region(), Some(b'J'));
+        assert_eq!(IPL3::Unknown(ipl).region(), None);
+    }
+
     #[test]
     fn offset_ipl3_6101() {
         let ipl3 = IPL3::Cic6101([0; IPL_SIZE]);
@@ -320,4 +977,10 @@ mod tests {
         let ipl3 = IPL3::Cic7102([0; IPL_SIZE]);
         assert_eq!(ipl3.offset(0x8000_0400), 0x8000_0400);
     }
+
+    #[test]
+    fn offset_ipl3_8303() {
+        let ipl3 = IPL3::Cic8303([0; IPL_SIZE]);
+        assert_eq!(ipl3.offset(0x8000_0400), 0x8000_0400);
+    }
 }
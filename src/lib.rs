@@ -1,28 +1,45 @@
 #![deny(clippy::all)]
-#![feature(backtrace)]
+#![cfg_attr(feature = "backtrace", feature(backtrace))]
 #![forbid(unsafe_code)]
 
 mod cargo;
 mod cli;
+mod completions;
 mod elf;
+mod emulator;
 mod fs;
 mod header;
 mod ipl3;
-
-use crate::cargo::SubcommandError;
-use crate::cli::{parse_args, ArgParseError, BuildArgs, Subcommand};
-use crate::elf::ElfError;
-use crate::fs::FSError;
-use crate::header::{N64Header, HEADER_SIZE};
-use crate::ipl3::{IPL_SIZE, PROGRAM_SIZE};
-use colored::Colorize;
+mod save;
+#[cfg(test)]
+mod test_util;
+
+use crate::cargo::{CargoArtifact, CargoArtifactTarget, SubcommandError};
+use crate::cli::{
+    json_requested, parse_args, ArgParseError, BuildArgs, FixCrcArgs, InspectArgs, MessageFormat,
+    Subcommand,
+};
+use crate::elf::{DumpOptions, ElfDump, ElfError, Symbol};
+use crate::emulator::EmulatorError;
+use crate::fs::{FSError, FSOptions};
+use crate::header::{header_name, HeaderError, N64Header, CRC_OFFSET, HEADER_SIZE, NAME_SIZE};
+use colored::{Color, Colorize};
 use error_iter::ErrorIter;
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::cmp;
-use std::path::PathBuf;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::time::Instant;
 use thiserror::Error;
 
+pub use crate::header::HeaderOptions;
+pub use crate::ipl3::{IPL3Error, IPL3Kind, IPL3, IPL_SIZE, PAD_BYTE, PROGRAM_SIZE};
+pub use crate::save::SaveType;
+
 #[derive(Debug, Error)]
 pub enum RunError {
     #[error("Argument parsing error")]
@@ -30,10 +47,40 @@ pub enum RunError {
 
     #[error("Build error")]
     BuildError(#[from] BuildError),
+
+    #[error("CRC fix error")]
+    FixCrcError(#[from] FixCrcError),
+
+    #[error("Inspect error")]
+    InspectError(#[from] InspectError),
+
+    #[error("Emulator error")]
+    EmulatorError(#[from] EmulatorError),
 }
 
 impl ErrorIter for RunError {}
 
+impl RunError {
+    /// Process exit code for this error, so scripts can tell failures apart:
+    ///
+    /// - `2`: invalid command line arguments
+    /// - `3`: `cargo build` failed
+    /// - `4`: the ELF could not be converted
+    /// - `5`: the program or ROM is too large
+    /// - `1`: any other error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunError::ArgParseError(_) => 2,
+            RunError::BuildError(BuildError::SubcommandError(_)) => 3,
+            RunError::BuildError(BuildError::ElfError(_))
+            | RunError::BuildError(BuildError::EntryPointError(_)) => 4,
+            RunError::BuildError(BuildError::ProgramTooBigError(_))
+            | RunError::BuildError(BuildError::PadToError(_, _)) => 5,
+            _ => 1,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum BuildError {
     #[error("Subcommand failed")]
@@ -45,8 +92,8 @@ pub enum BuildError {
     #[error("Error while creating filesystem")]
     FSError(#[from] FSError),
 
-    #[error("Elf program is larger than 1MB")]
-    ProgramTooBigError,
+    #[error("Elf program is larger than {0} bytes")]
+    ProgramTooBigError(usize),
 
     #[error("Empty filename")]
     EmptyFilenameError,
@@ -56,8 +103,62 @@ pub enum BuildError {
 
     #[error("Could not create file `{0}`")]
     CreateFileError(String),
+
+    #[error("Could not hash ROM image `{0}`")]
+    HashError(String),
+
+    #[error("Could not write ROM image to stdout")]
+    StdoutError,
+
+    #[error("`--output` cannot be used when building multiple ROMs")]
+    AmbiguousOutputError,
+
+    #[error("{0}")]
+    EntryPointError(String),
+
+    #[error("{0}")]
+    UnknownIPL3Error(String),
+
+    #[error("Error recomputing CRCs")]
+    FixCrcError(#[from] FixCrcError),
+
+    #[error("Could not read embedded file `{0}`")]
+    EmbedReadError(String),
+
+    #[error("Embedded file at {0:#x} overlaps the ROM contents ending at {1:#x}")]
+    EmbedOverlapError(usize, usize),
+
+    #[error("ROM content is {0} bytes, larger than the `--pad-to` size of {1} bytes")]
+    PadToError(usize, usize),
+
+    #[error(
+        "Program name `{0}` contains non-ASCII character `{1}`, use `--name` to set an ASCII name"
+    )]
+    NameEncodingError(String, char),
+}
+
+#[derive(Debug, Error)]
+pub enum FixCrcError {
+    #[error("I/O error")]
+    Io(#[from] io::Error),
+
+    #[error("ROM is too small, expected at least {0} bytes")]
+    RomTooSmallError(usize),
+
+    #[error("Header parsing error")]
+    HeaderError(#[from] HeaderError),
+}
+
+#[derive(Debug, Error)]
+pub enum InspectError {
+    #[error("I/O error")]
+    Io(#[from] io::Error),
+
+    #[error("CRC check error")]
+    CrcError(#[from] FixCrcError),
 }
 
+#[cfg(feature = "backtrace")]
 fn print_backtrace(error: &dyn std::error::Error) {
     if let Some(backtrace) = error.backtrace() {
         let backtrace = backtrace.to_string();
@@ -67,6 +168,21 @@ fn print_backtrace(error: &dyn std::error::Error) {
     }
 }
 
+/// Backtraces are only available with the nightly `backtrace` feature, the
+/// error chain is printed regardless.
+#[cfg(not(feature = "backtrace"))]
+fn print_backtrace(_error: &dyn std::error::Error) {}
+
+/// Process exit code for `error`, see [`RunError::exit_code`]. Other errors
+/// exit with `1`.
+fn exit_code<E: ErrorIter>(error: &E) -> i32 {
+    error
+        .chain()
+        .next()
+        .and_then(|error| error.downcast_ref::<RunError>())
+        .map_or(1, RunError::exit_code)
+}
+
 pub fn handle_errors<E, R, T>(run: R, args: &[T])
 where
     E: std::error::Error + ErrorIter,
@@ -76,6 +192,12 @@ where
     let start = Instant::now();
 
     match run(args) {
+        Err(e) if json_requested(args) => {
+            let causes: Vec<_> = e.chain().skip(1).map(|cause| cause.to_string()).collect();
+            println!("{}", json!({ "error": e.to_string(), "causes": causes }));
+
+            process::exit(exit_code(&e));
+        }
         Err(e) => {
             eprintln!("{} {}", "error:".red(), e);
             print_backtrace(&e);
@@ -85,7 +207,7 @@ where
                 print_backtrace(cause);
             }
 
-            process::exit(1);
+            process::exit(exit_code(&e));
         }
         Ok(print_status) => {
             if print_status {
@@ -102,110 +224,753 @@ where
 /// This is the entrypoint. It is responsible for parsing the cli args common to
 /// all subcommands, and ultimately executing the requested subcommand.
 pub fn run<T: AsRef<str>>(args: &[T]) -> Result<bool, RunError> {
+    // Completions are a hidden subcommand, so they are not part of the usage text
+    if let Some(shell) = completions::requested(args) {
+        print!("{}", completions::generate(shell)?);
+        return Ok(false);
+    }
+
     let args = parse_args(args)?;
 
-    if let Some(Subcommand::Build(build_args)) = args.subcommand {
-        build(build_args, args.verbose)?;
-    } else if args.version {
-        println!(concat!("cargo-n64 version ", env!("CARGO_PKG_VERSION")));
+    match args.subcommand {
+        Some(Subcommand::Build(build_args)) | Some(Subcommand::Elf2Rom(build_args)) => {
+            let format = args.message_format.unwrap_or_default();
+            build(build_args, args.verbose, args.quiet, format)?;
+
+            // The status line is not part of the JSON output
+            return Ok(format == MessageFormat::Human && !args.quiet);
+        }
+        Some(Subcommand::Run(build_args)) => {
+            // Fail early when there is no emulator to run the ROM with
+            let emulator = emulator::command(build_args.emulator.as_deref())?;
+            if build_args.output.as_deref() == Some("-") {
+                return Err(EmulatorError::StdoutRom.into());
+            }
+
+            // Only one ROM can be run, so don't convert any when there are more
+            let [artifact]: [CargoArtifact; 1] = artifacts(&build_args, args.verbose, args.quiet)?
+                .try_into()
+                .map_err(|_| EmulatorError::AmbiguousRom)?;
+
+            let format = args.message_format.unwrap_or_default();
+            let rom = build_artifact(&build_args, artifact, args.verbose, args.quiet, format)?;
+            emulator::run(&emulator, &rom, args.verbose)?;
+
+            return Ok(false);
+        }
+        Some(Subcommand::FixCrc(fix_crc_args)) => {
+            fix_crc(fix_crc_args)?;
+
+            return Ok(false);
+        }
+        Some(Subcommand::Inspect(inspect_args)) => {
+            inspect(inspect_args)?;
+
+            return Ok(false);
+        }
+        None => {
+            if args.version {
+                println!(concat!("cargo-n64 version ", env!("CARGO_PKG_VERSION")));
+            }
+        }
     }
 
     Ok(true)
 }
 
 /// The build subcommand. Parses cli args specific to build, executes
-/// `cargo build-std`, and transforms the ELF to a ROM file.
-fn build(mut args: BuildArgs, verbose: usize) -> Result<(), BuildError> {
-    use self::BuildError::*;
+/// `cargo build-std` (unless a prebuilt ELF is given), and transforms each ELF
+/// to a ROM file. Returns the path of each ROM.
+fn build(
+    args: BuildArgs,
+    verbose: usize,
+    quiet: bool,
+    format: MessageFormat,
+) -> Result<Vec<PathBuf>, BuildError> {
+    let artifacts = artifacts(&args, verbose, quiet)?;
+
+    // A single output path can only hold one ROM
+    if args.output.is_some() && artifacts.len() > 1 {
+        return Err(BuildError::AmbiguousOutputError);
+    }
+
+    artifacts
+        .into_iter()
+        .map(|artifact| build_artifact(&args, artifact, verbose, quiet, format))
+        .collect()
+}
+
+/// Executes `cargo build-std`, or takes the prebuilt ELF given with `--elf`.
+/// Returns the artifacts to transform to ROM files.
+fn artifacts(
+    args: &BuildArgs,
+    verbose: usize,
+    quiet: bool,
+) -> Result<Vec<CargoArtifact>, BuildError> {
+    match args.elf {
+        Some(ref elf) => Ok(vec![elf_artifact(elf)?]),
+        None => {
+            if !quiet {
+                eprintln!("{:>12} with cargo build-std", "Building".green().bold());
+            }
+            let start = Instant::now();
+            let artifacts = cargo::run(args, verbose)?;
+            print_phase_time(verbose, "cargo build", start);
+
+            Ok(artifacts)
+        }
+    }
+}
 
-    eprintln!("{:>12} with cargo build-std", "Building".green().bold());
-    let artifact = cargo::run(&args, verbose)?;
+/// Transforms a single cargo build artifact to a ROM file, returning its path.
+fn build_artifact(
+    args: &BuildArgs,
+    artifact: CargoArtifact,
+    verbose: usize,
+    quiet: bool,
+    format: MessageFormat,
+) -> Result<PathBuf, BuildError> {
+    use self::BuildError::*;
 
     // Set default program name
-    args.name.get_or_insert(artifact.target.name);
-    let args = args;
+    let crate_name = artifact.target.name;
+    let name = args.name.clone().unwrap_or_else(|| crate_name.clone());
+    check_name(&name)?;
+    if let Some(message) = check_name_length(&name) {
+        eprintln!("{} {}", "warning:".yellow().bold(), message);
+    }
 
-    eprintln!("{:>12} ELF to binary", "Dumping".green().bold());
+    if !quiet {
+        eprintln!("{:>12} ELF to binary", "Dumping".green().bold());
+    }
+    let start = Instant::now();
     let filename = artifact.executable;
-    let (entry_point, program) = elf::dump(&filename)?;
+    let dump_options = DumpOptions {
+        endian: args.elf_endian.unwrap_or_default(),
+        skip_validation: args.skip_elf_validation,
+        symbols: args.emit_symbols,
+        sections: args.section.clone(),
+        verbose,
+    };
+    let ElfDump {
+        entry_point,
+        program,
+        symbols,
+        bss,
+    } = elf::dump(&filename, &dump_options)?;
+    print_phase_time(verbose, "ELF dump", start);
+
+    if verbose > 0 {
+        if let Some(bss) = bss {
+            eprintln!(
+                "{:>12} {:#010x}..{:#010x} ({} bytes) must be cleared at runtime",
+                "BSS".green().bold(),
+                bss.start,
+                bss.end,
+                bss.len()
+            );
+        }
+    }
 
     // XXX: See https://github.com/rust-console/cargo-n64/issues/40
-    if program.len() > 1024 * 1024 {
-        return Err(ProgramTooBigError);
+    check_program_size(&program, args.max_program_size.unwrap_or(PROGRAM_SIZE))?;
+
+    if let Some(message) = check_entry_point(entry_point, args.get_ipl3().unwrap()) {
+        if args.strict {
+            return Err(EntryPointError(message));
+        }
+        eprintln!("{} {}", "warning:".yellow().bold(), message);
+    }
+
+    if let Some(message) = check_ipl3(args.get_ipl3().unwrap()) {
+        if args.strict {
+            return Err(UnknownIPL3Error(message));
+        }
+        eprintln!("{} {}", "warning:".yellow().bold(), message);
+    }
+    if let Some(message) = check_assumed_cic(args.get_ipl3().unwrap()) {
+        eprintln!("{} {}", "warning:".yellow().bold(), message);
+    }
+
+    let path = match args.output {
+        Some(ref output) => PathBuf::from(output),
+        None => get_output_filename(&filename)?,
+    };
+    let start = Instant::now();
+    let fs = get_filesystem(args, quiet)?;
+    if fs.is_some() {
+        print_phase_time(verbose, "file system", start);
+    }
+
+    if args.emit_symbols && !args.dry_run {
+        // Symbols go next to the ELF when the ROM is written to stdout
+        let sym_path = if path == Path::new("-") {
+            get_output_filename(&filename)?.with_extension("sym")
+        } else {
+            path.with_extension("sym")
+        };
+
+        if !quiet {
+            eprintln!(
+                "{:>12} symbols to `{}`",
+                "Writing".green().bold(),
+                sym_path.display()
+            );
+        }
+        write_symbols(&sym_path, &symbols)?;
+    }
+
+    if !quiet {
+        eprintln!("{:>12} final ROM image", "Building".green().bold());
+    }
+    let rom_path = path.clone();
+    let info = create_rom_image(path, args, &crate_name, entry_point, program, fs, verbose)?;
+
+    if args.dry_run {
+        if !quiet {
+            eprintln!(
+                "{:>12} `{}` ({} bytes, {})",
+                "Would write".green().bold(),
+                rom_path.display(),
+                info.size,
+                format_crcs(info.crcs)
+            );
+        }
+        if format == MessageFormat::Json {
+            println!(
+                "{}",
+                build_message(&rom_path, &info, args.get_ipl3().unwrap())
+            );
+        }
+
+        return Ok(rom_path);
     }
 
-    let path = get_output_filename(&filename)?;
-    let fs = args
-        .fs
-        .as_ref()
-        .map(|fs_path| {
+    if args.emit_manifest {
+        // The manifest goes next to the ELF when the ROM is written to stdout
+        let manifest_path = if rom_path == Path::new("-") {
+            get_output_filename(&filename)?.with_extension("json")
+        } else {
+            rom_path.with_extension("json")
+        };
+
+        if !quiet {
+            eprintln!(
+                "{:>12} layout manifest to `{}`",
+                "Writing".green().bold(),
+                manifest_path.display()
+            );
+        }
+        std::fs::write(&manifest_path, layout_manifest(&info.layout).to_string())
+            .map_err(|_| CreateFileError(manifest_path.to_string_lossy().to_string()))?;
+    }
+
+    if args.emit_hash {
+        let hash_path = get_hash_filename(&rom_path);
+        if !quiet {
+            eprintln!(
+                "{:>12} SHA-256 to `{}`",
+                "Writing".green().bold(),
+                hash_path.display()
+            );
+        }
+        write_hash(&rom_path, &hash_path)?;
+    }
+
+    if format == MessageFormat::Json {
+        println!(
+            "{}",
+            build_message(&rom_path, &info, args.get_ipl3().unwrap())
+        );
+    }
+
+    if verbose > 0 {
+        eprintln!("{:>12} {}", "CIC".green().bold(), args.get_ipl3().unwrap());
+        eprintln!(
+            "{:>12} {:#010x}",
+            "Entry point".green().bold(),
+            info.entry_point
+        );
+        eprintln!("{:>12} {} bytes", "ROM size".green().bold(), info.size);
+    }
+    if verbose > 0 || args.show_crc {
+        eprintln!(
+            "{:>12} {}",
+            "Checksum".green().bold(),
+            format_crcs(info.crcs)
+        );
+    }
+
+    Ok(rom_path)
+}
+
+/// Lowest address in RDRAM the program can be loaded at, past the exception
+/// vectors. This is where the bundled linker script places `.boot`.
+const LOAD_ADDRESS: u32 = 0x8000_0400;
+
+/// End of the 8 MiB of RDRAM with the expansion pak, in KSEG0
+const RDRAM_END: u32 = 0x8080_0000;
+
+/// Sanity check the ELF entry point against the selected CIC. The header entry
+/// point is offset for CIC 6103 and 6106, so an ELF already linked at the offset
+/// address would be offset twice and jump into the weeds.
+fn check_entry_point(entry_point: u32, ipl3: &IPL3) -> Option<String> {
+    let offset = ipl3.entry_offset();
+    let header_entry_point = ipl3.offset(entry_point);
+
+    if !(LOAD_ADDRESS..RDRAM_END).contains(&entry_point) {
+        Some(format!(
+            "Entry point {:#010x} is outside of RDRAM, expected `.boot` at {:#010x}",
+            entry_point, LOAD_ADDRESS
+        ))
+    } else if offset != 0 && entry_point >= LOAD_ADDRESS + offset {
+        Some(format!(
+            "Entry point {:#010x} looks like it already includes the {:#x} offset for {}, \
+             the header entry point would be {:#010x}",
+            entry_point, offset, ipl3, header_entry_point
+        ))
+    } else if header_entry_point >= RDRAM_END {
+        Some(format!(
+            "Entry point {:#010x} is offset to {:#010x} for {}, which is outside of RDRAM",
+            entry_point, header_entry_point, ipl3
+        ))
+    } else {
+        None
+    }
+}
+
+/// Warn about an unrecognized IPL3. The CRC seed depends on the CIC, so the
+/// checksums of an unknown bootcode are a guess and the ROM will likely not boot.
+fn check_ipl3(ipl3: &IPL3) -> Option<String> {
+    if ipl3.kind().is_some() {
+        return None;
+    }
+
+    Some(format!(
+        "IPL3 with CRC32 {:08x} is not recognized, the ROM checksums cannot be computed \
+         with the correct seed and the ROM will likely not boot. Please report the CRC32 \
+         if this bootcode should be supported",
+        crc32fast::hash(ipl3.get_ipl())
+    ))
+}
+
+/// Warn when an unrecognized IPL3 was paired with a known CIC, which happens
+/// when `--ipl3-from-rom` matches the CRCs in the source ROM header.
+fn check_assumed_cic(ipl3: &IPL3) -> Option<String> {
+    let kind = ipl3.kind()?;
+    if IPL3::check(*ipl3.get_ipl()).kind().is_some() {
+        return None;
+    }
+
+    Some(format!(
+        "IPL3 with CRC32 {:08x} is not recognized, assuming it uses the checksum seed of \
+         {} because that matches the CRCs of the ROM it was read from",
+        crc32fast::hash(ipl3.get_ipl()),
+        kind.name()
+    ))
+}
+
+/// Offset and length of each ROM region, for `--emit-manifest`.
+fn layout_manifest(layout: &RomLayout) -> serde_json::Value {
+    let region = |region: RomRegion| json!({ "off": region.offset, "len": region.length });
+
+    json!({
+        "header": region(layout.header),
+        "ipl3": region(layout.ipl3),
+        "program": region(layout.program),
+        "fs": region(layout.fs),
+        "embeds": region(layout.embeds),
+        "padding": region(layout.padding),
+        "save": region(layout.save),
+    })
+}
+
+/// Machine-readable summary of a built ROM, for `--message-format=json`.
+fn build_message(path: &Path, info: &RomInfo, ipl3: &IPL3) -> serde_json::Value {
+    json!({
+        "rom": path.to_string_lossy(),
+        "size": info.size,
+        "crc1": info.crcs.0,
+        "crc2": info.crcs.1,
+        "cic": ipl3.to_string(),
+    })
+}
+
+/// Creates the embedded file system from `--fs`, or reads a prebuilt image from
+/// `--fs-image`.
+fn get_filesystem(args: &BuildArgs, quiet: bool) -> Result<Option<Vec<u8>>, BuildError> {
+    if args.no_fs {
+        return Ok(None);
+    }
+
+    if let Some(ref image_path) = args.fs_image {
+        if !quiet {
+            eprintln!(
+                "{:>12} file system image `{}` to the ROM image",
+                "Appending".green().bold(),
+                image_path,
+            );
+        }
+
+        let image = std::fs::read(image_path).map_err(FSError::from)?;
+
+        return Ok(Some(image));
+    }
+
+    if args.fs.is_empty() {
+        return Ok(None);
+    }
+
+    if !quiet {
+        for fs_path in &args.fs {
             eprintln!(
                 "{:>12} file system at `{}` to the ROM image",
                 "Appending".green().bold(),
                 fs_path,
             );
+        }
+    }
+
+    let options = FSOptions {
+        max_depth: args.fs_max_depth.unwrap_or(fs::DEFAULT_MAX_DEPTH),
+        label: args.fs_label.unwrap_or(fs::DEFAULT_LABEL),
+        exclude: args.fs_exclude.clone(),
+        dotfiles: args.fs_dotfiles,
+        reserved: args.fs_reserved,
+    };
+
+    Ok(Some(fs::create_filesystem(&args.fs, &options)?))
+}
+
+/// Writes symbols in a simple `address name` text format, one per line.
+fn write_symbols(path: &Path, symbols: &[Symbol]) -> Result<(), BuildError> {
+    use self::BuildError::*;
+
+    let contents: String = symbols
+        .iter()
+        .map(|symbol| format!("{:08x} {}\n", symbol.address, symbol.name))
+        .collect();
+
+    std::fs::write(path, contents).map_err(|_| CreateFileError(path.to_string_lossy().to_string()))
+}
+
+/// Hashes the ROM file as written, so the result matches `sha256sum`. The ROM is
+/// referenced by file name, so `sha256sum -c` works from the ROM directory.
+fn write_hash(rom_path: &Path, hash_path: &Path) -> Result<(), BuildError> {
+    use self::BuildError::*;
+
+    let mut hasher = Sha256::new();
+    File::open(rom_path)
+        .and_then(|mut file| io::copy(&mut file, &mut hasher))
+        .map_err(|_| HashError(rom_path.to_string_lossy().to_string()))?;
+
+    let file_name = rom_path.file_name().ok_or(EmptyFilenameError)?;
+    let contents = format!(
+        "{}  {}\n",
+        hex(&hasher.finalize()),
+        file_name.to_string_lossy()
+    );
+
+    std::fs::write(hash_path, contents)
+        .map_err(|_| CreateFileError(hash_path.to_string_lossy().to_string()))
+}
+
+/// Lowercase hex encoding.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The fix-crc subcommand. Recomputes the CRCs of an existing ROM and patches
+/// only the CRC fields in its header, leaving every other byte untouched.
+fn fix_crc(args: FixCrcArgs) -> Result<(), FixCrcError> {
+    let path = args.rom.unwrap();
+    let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+
+    let mut rom = Vec::new();
+    file.read_to_end(&mut rom)?;
+
+    let (ipl3, old_crcs, new_crcs) = check_rom_crcs(&rom)?;
+    eprintln!(
+        "{:>12} CRCs of `{}` with {}",
+        "Fixing".green().bold(),
+        path,
+        ipl3
+    );
+    eprintln!("{:>12} {}", "Old".green().bold(), format_crcs(old_crcs));
+    eprintln!("{:>12} {}", "New".green().bold(), format_crcs(new_crcs));
+
+    file.seek(SeekFrom::Start(CRC_OFFSET as u64))?;
+    file.write_all(&new_crcs.0.to_be_bytes())?;
+    file.write_all(&new_crcs.1.to_be_bytes())?;
+
+    Ok(())
+}
+
+/// Recomputes the CRCs of a complete ROM image and writes them to its header,
+/// returning the new CRCs. This is the library counterpart to `fix-crc`.
+pub fn recompute_crcs(rom: &mut [u8]) -> Result<(u32, u32), BuildError> {
+    let (_, _, crcs) = check_rom_crcs(rom)?;
+
+    rom[CRC_OFFSET..CRC_OFFSET + 4].copy_from_slice(&crcs.0.to_be_bytes());
+    rom[CRC_OFFSET + 4..CRC_OFFSET + 8].copy_from_slice(&crcs.1.to_be_bytes());
+
+    Ok(crcs)
+}
+
+/// The inspect subcommand. Shows the IPL3 and CRCs of an existing ROM, and
+/// optionally which known CICs the stored CRCs are valid for.
+fn inspect(args: InspectArgs) -> Result<(), InspectError> {
+    let path = args.rom.unwrap();
+    let rom = std::fs::read(&path)?;
+
+    let (ipl3, stored_crcs, computed_crcs) = check_rom_crcs(&rom)?;
+    eprintln!("{:>12} `{}`", "Inspecting".green().bold(), path);
+    eprintln!("{:>12} {}", "IPL3".green().bold(), ipl3);
+    eprintln!(
+        "{:>12} {}",
+        "Stored".green().bold(),
+        format_crcs_diff(stored_crcs, computed_crcs, Color::Red)
+    );
+    eprintln!(
+        "{:>12} {}",
+        "Computed".green().bold(),
+        format_crcs_diff(computed_crcs, stored_crcs, Color::Green)
+    );
+
+    if args.crc_scan {
+        let matches = scan_rom_crcs(&rom)?;
+        if matches.is_empty() {
+            eprintln!(
+                "{} No known CIC matches the stored CRCs",
+                "warning:".yellow().bold()
+            );
+        } else {
+            let names: Vec<_> = matches.iter().map(IPL3::to_string).collect();
+            eprintln!("{:>12} {}", "Matches".green().bold(), names.join(", "));
+        }
+    }
+
+    Ok(())
+}
+
+/// Recomputes the CRCs of a complete ROM image with every known CIC, and
+/// returns those that match the CRCs stored in the header. CICs sharing a
+/// checksum algorithm (e.g. 6101, 6102 and 7102) always match together.
+fn scan_rom_crcs(rom: &[u8]) -> Result<Vec<IPL3>, FixCrcError> {
+    let (ipl3, stored_crcs, _) = check_rom_crcs(rom)?;
+    let program = &rom[HEADER_SIZE + IPL_SIZE..];
+
+    let mut matches = Vec::new();
+    for candidate in IPL3::known(*ipl3.get_ipl()) {
+        if candidate.compute_crcs_reader(program, program.len())? == stored_crcs {
+            matches.push(candidate);
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Formats header CRCs as `CRC1: 0x........ CRC2: 0x........`.
+fn format_crcs((crc1, crc2): (u32, u32)) -> String {
+    format!("CRC1: {:#010x} CRC2: {:#010x}", crc1, crc2)
+}
+
+/// Formats header CRCs like `format_crcs`, highlighting each CRC that differs
+/// from the one in `other` with `color`.
+fn format_crcs_diff(
+    (crc1, crc2): (u32, u32),
+    (other1, other2): (u32, u32),
+    color: Color,
+) -> String {
+    let format_crc = |crc: u32, other: u32| {
+        let text = format!("{:#010x}", crc);
+        if crc == other {
+            text
+        } else {
+            text.color(color).bold().to_string()
+        }
+    };
+
+    format!(
+        "CRC1: {} CRC2: {}",
+        format_crc(crc1, other1),
+        format_crc(crc2, other2)
+    )
+}
+
+/// Detects the IPL3 of a complete ROM image and returns it along with the CRCs
+/// stored in the header and the CRCs recomputed over the program.
+fn check_rom_crcs(rom: &[u8]) -> Result<(IPL3, (u32, u32), (u32, u32)), FixCrcError> {
+    use self::FixCrcError::*;
+
+    let program_offset = HEADER_SIZE + IPL_SIZE;
+    if rom.len() < program_offset {
+        return Err(RomTooSmallError(program_offset));
+    }
 
-            fs::create_filesystem(fs_path)
-        })
-        .transpose()?;
+    let ipl3 = IPL3::check(rom[HEADER_SIZE..program_offset].try_into().unwrap());
+    let program = &rom[program_offset..];
+    let crcs = ipl3.compute_crcs_reader(program, program.len())?;
 
-    eprintln!("{:>12} final ROM image", "Building".green().bold());
-    create_rom_image(path, &args, entry_point, program, fs)
+    Ok((ipl3, N64Header::from_bytes(rom)?.crcs(), crcs))
 }
 
-const PAD_BYTE: u8 = 0xFF;
 const MULTIPLE: usize = 4 * 1024 * 1024;
 
 /// Align a byte buffer
-fn align_to(buffer: &mut Vec<u8>, alignment: usize) {
+fn align_to(buffer: &mut Vec<u8>, alignment: usize, pad_byte: u8) {
     let alignment = alignment - 1;
     let length = (buffer.len() + alignment) & !alignment;
 
-    buffer.resize(length, PAD_BYTE);
+    buffer.resize(length, pad_byte);
 }
 
-/// Pads the program to its minimum required size for CRC calculation
-fn pad_program(program: &mut Vec<u8>) {
-    program.resize(cmp::max(PROGRAM_SIZE, program.len()), PAD_BYTE);
+/// Pads the program to its minimum required size for CRC calculation.
+///
+/// Programs larger than `PROGRAM_SIZE` are left untouched. The IPL3 only
+/// checksums the first `PROGRAM_SIZE` bytes, so anything beyond that boundary
+/// is not covered by the CRCs in the header.
+fn pad_program(program: &mut Vec<u8>, pad_byte: u8) {
+    program.resize(cmp::max(PROGRAM_SIZE, program.len()), pad_byte);
 }
 
 /// Pads the ROM to a power of 2, or a multiple of 4 MiB. Whichever is smallest.
-fn pad_rom(rom: &mut Vec<u8>) {
-    let size = cmp::max(HEADER_SIZE + IPL_SIZE + PROGRAM_SIZE, rom.len()) as f64;
+/// An explicit `pad_to` size is used instead when given.
+fn pad_rom(rom: &mut Vec<u8>, pad_to: Option<usize>, pad_byte: u8) -> Result<(), BuildError> {
+    rom.resize(rom_size(rom.len(), pad_to)?, pad_byte);
+
+    Ok(())
+}
+
+/// The final size of a ROM with `length` bytes of content, which is `pad_to`
+/// when given. It is an error for the content to exceed `pad_to`.
+fn rom_size(length: usize, pad_to: Option<usize>) -> Result<usize, BuildError> {
+    match pad_to {
+        Some(size) if size < length => Err(BuildError::PadToError(length, size)),
+        Some(size) => Ok(size),
+        None => Ok(padded_rom_size(length)),
+    }
+}
+
+/// The final size of a ROM with `length` bytes of content. See [`pad_rom`].
+fn padded_rom_size(length: usize) -> usize {
+    let size = cmp::max(HEADER_SIZE + IPL_SIZE + PROGRAM_SIZE, length) as f64;
 
     let by_power_of_2 = 2.0f64.powf(size.log2().ceil());
     let by_multiple = (size / MULTIPLE as f64).ceil() * MULTIPLE as f64;
 
-    rom.resize(
-        cmp::min(by_power_of_2 as usize, by_multiple as usize),
-        PAD_BYTE,
-    );
+    cmp::min(by_power_of_2 as usize, by_multiple as usize)
 }
 
-/// Creates a ROM image, generating the header and IPL3 from `args`. An optional
-/// file system (FAT image) is appended to the ROM image if provided.
-fn create_rom_image(
-    path: PathBuf,
-    args: &BuildArgs,
-    entry_point: u32,
-    mut program: Vec<u8>,
-    fs: Option<Vec<u8>>,
-) -> Result<(), BuildError> {
-    use self::BuildError::*;
+/// Options for building a ROM image with [`build_rom`].
+#[derive(Debug, Clone)]
+pub struct RomOptions {
+    /// Program name written to the header.
+    pub name: String,
 
-    let mut fs = fs.unwrap_or_default();
+    /// IPL3 (bootcode) to embed, also selects the CRC algorithm.
+    pub ipl3: IPL3,
+
+    /// Region code, e.g. `b'E'` for USA/English.
+    pub region: u8,
+
+    /// Two-byte cartridge ID.
+    pub cart_id: [u8; 2],
+
+    /// Append a zeroed save region of this type after the padded ROM.
+    pub save: Option<SaveType>,
 
-    pad_program(&mut program);
-    align_to(&mut fs, std::mem::size_of::<u32>());
+    /// Cartridge timing and clock rate fields.
+    pub header: HeaderOptions,
 
-    let program = program;
-    let fs = fs;
+    /// Align the start of the file system within the ROM to this power of 2.
+    /// The padding follows the program, past the region covered by the CRCs.
+    pub fs_align: Option<usize>,
+
+    /// Pad the ROM to exactly this size, instead of the next power of 2 or
+    /// multiple of 4 MiB.
+    pub pad_to: Option<usize>,
+
+    /// Fill byte for all padding, `0xFF` by default. The program is padded
+    /// before the CRCs are computed, so they always cover the bytes written.
+    pub pad_byte: u8,
+
+    /// Files placed at fixed ROM offsets after the file system, as `(offset,
+    /// contents)`. The program always fills the CRC window, so embedded files
+    /// are never covered by the CRCs.
+    pub embeds: Vec<(usize, Vec<u8>)>,
+
+    /// Largest program accepted from an ELF, `PROGRAM_SIZE` by default. Same
+    /// as `--max-program-size`.
+    pub max_program_size: usize,
+}
+
+impl RomOptions {
+    /// Create ROM options with the default region (USA/English) and cartridge ID.
+    pub fn new(name: impl Into<String>, ipl3: IPL3) -> RomOptions {
+        RomOptions {
+            name: name.into(),
+            ipl3,
+            region: b'E',
+            cart_id: *b"KW", // KodeWerx!
+            save: None,
+            header: HeaderOptions::default(),
+            fs_align: None,
+            pad_to: None,
+            pad_byte: PAD_BYTE,
+            embeds: Vec::new(),
+            max_program_size: PROGRAM_SIZE,
+        }
+    }
+}
+
+/// Flashcart menus and emulators display the header name as ASCII, so reject
+/// anything else rather than writing raw UTF-8 bytes.
+fn check_name(name: &str) -> Result<(), BuildError> {
+    match name.chars().find(|&c| c != ' ' && !c.is_ascii_graphic()) {
+        Some(c) => Err(BuildError::NameEncodingError(name.to_owned(), c)),
+        None => Ok(()),
+    }
+}
+
+/// Warn when the program name does not fit the header and will be truncated,
+/// showing the name as it will be written.
+fn check_name_length(name: &str) -> Option<String> {
+    if name.len() <= NAME_SIZE {
+        return None;
+    }
+
+    Some(format!(
+        "Program name `{}` is longer than {} bytes and will be truncated to `{}`",
+        name,
+        NAME_SIZE,
+        String::from_utf8_lossy(&header_name(name))
+    ))
+}
+
+/// Derives a two-letter cartridge ID from a crate name, so each project gets a
+/// stable ID of its own. Both bytes are uppercase ASCII letters.
+pub fn cart_id_from_name(name: &str) -> [u8; 2] {
+    let hash = crc32fast::hash(name.as_bytes());
+
+    [b'A' + (hash % 26) as u8, b'A' + (hash / 26 % 26) as u8]
+}
+
+/// Builds a complete ROM image in memory, generating the header and IPL3 from
+/// `options`. An optional file system (FAT image) is appended to the ROM image
+/// if provided. The returned image is padded to its final size.
+pub fn build_rom(
+    entry_point: u32,
+    program: Vec<u8>,
+    fs: Option<Vec<u8>>,
+    options: RomOptions,
+) -> Result<Vec<u8>, BuildError> {
+    check_name(&options.name)?;
+    let (program, fs) = pad_contents(program, fs, &options);
 
-    let name = args.name.as_ref().unwrap();
-    let ipl3 = args.ipl3.as_ref().unwrap();
     let mut rom = [
-        &N64Header::new(entry_point, name, &program, &fs, ipl3).to_vec()[..],
-        ipl3.get_ipl(),
+        &N64Header::new(entry_point, &program, &fs, &options).to_vec()[..],
+        options.ipl3.get_ipl(),
         &program,
         &fs,
     ]
@@ -216,13 +981,351 @@ fn create_rom_image(
         acc
     });
 
-    pad_rom(&mut rom);
+    let (embeds, _) = embed_contents(rom.len(), &options.embeds)?;
+    for (offset, data) in embeds {
+        rom.resize(offset, options.pad_byte);
+        rom.extend_from_slice(data);
+    }
+
+    pad_rom(&mut rom, options.pad_to, options.pad_byte)?;
+    if let Some(save) = options.save {
+        rom.resize(rom.len() + save.size(), 0);
+    }
+
+    Ok(rom)
+}
+
+/// Builds a complete ROM image from the bytes of an ELF executable, without
+/// invoking cargo or touching the disk. The ELF is validated and dumped with the
+/// same rules as `cargo n64 build`. See [`build_rom`].
+pub fn build_rom_from_elf_bytes(elf: &[u8], options: RomOptions) -> Result<Vec<u8>, BuildError> {
+    let ElfDump {
+        entry_point,
+        program,
+        ..
+    } = elf::dump_bytes(elf, &DumpOptions::default())?;
 
-    std::fs::write(&path, &rom).map_err(|_| CreateFileError(path.to_string_lossy().to_string()))?;
+    check_program_size(&program, options.max_program_size)?;
+
+    build_rom(entry_point, program, None, options)
+}
+
+/// Reject a program dumped from an ELF that is larger than `max_program_size`.
+fn check_program_size(program: &[u8], max_program_size: usize) -> Result<(), BuildError> {
+    if program.len() > max_program_size {
+        return Err(BuildError::ProgramTooBigError(max_program_size));
+    }
 
     Ok(())
 }
 
+/// Summary of a ROM image written by [`stream_rom`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomInfo {
+    /// CRC1 and CRC2 written to the header.
+    pub crcs: (u32, u32),
+
+    /// Entry point written to the header, offset for the IPL3.
+    pub entry_point: u32,
+
+    /// Total size of the image in bytes, including padding and any save region.
+    pub size: usize,
+
+    /// Location of each region within the image.
+    pub layout: RomLayout,
+}
+
+/// Byte range of one region of a ROM image.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RomRegion {
+    /// Offset from the start of the image.
+    pub offset: usize,
+
+    /// Length in bytes, may be zero.
+    pub length: usize,
+}
+
+/// Location of each region of a ROM image, in order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RomLayout {
+    /// The 64-byte header.
+    pub header: RomRegion,
+
+    /// The IPL3 (bootcode).
+    pub ipl3: RomRegion,
+
+    /// The program, including padding up to its minimum size and `fs_align`.
+    pub program: RomRegion,
+
+    /// The file system, including alignment to a 4-byte boundary.
+    pub fs: RomRegion,
+
+    /// Embedded files, including the padding before each one.
+    pub embeds: RomRegion,
+
+    /// Padding up to the final ROM size.
+    pub padding: RomRegion,
+
+    /// The empty save region.
+    pub save: RomRegion,
+}
+
+/// Writes a complete ROM image to `writer` one part at a time, without holding
+/// the whole image in memory. The output is identical to [`build_rom`].
+pub fn stream_rom<W: Write + ?Sized>(
+    writer: &mut W,
+    entry_point: u32,
+    program: Vec<u8>,
+    fs: Option<Vec<u8>>,
+    options: &RomOptions,
+) -> io::Result<RomInfo> {
+    check_name(&options.name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let (program, fs) = pad_contents(program, fs, options);
+    let header = N64Header::new(entry_point, &program, &fs, options);
+
+    write_contents(writer, header, program, fs, options)
+}
+
+/// Writes a ROM image for the `header` computed from the padded contents.
+fn write_contents<W: Write + ?Sized>(
+    writer: &mut W,
+    header: N64Header,
+    program: Vec<u8>,
+    fs: Vec<u8>,
+    options: &RomOptions,
+) -> io::Result<RomInfo> {
+    let crcs = header.crcs();
+    let entry_point = header.entry_point();
+    let header = header.to_vec();
+
+    // Each region starts where the previous one ends
+    let invalid = |e: BuildError| io::Error::new(io::ErrorKind::InvalidInput, e.to_string());
+    let contents = header.len() + IPL_SIZE + program.len() + fs.len();
+    let (embeds, length) = embed_contents(contents, &options.embeds).map_err(invalid)?;
+    let size = rom_size(length, options.pad_to).map_err(invalid)?;
+    let mut offset = 0;
+    let mut region = |length| {
+        let region = RomRegion { offset, length };
+        offset += length;
+
+        region
+    };
+    let layout = RomLayout {
+        header: region(header.len()),
+        ipl3: region(IPL_SIZE),
+        program: region(program.len()),
+        fs: region(fs.len()),
+        embeds: region(length - contents),
+        padding: region(size - length),
+        save: region(options.save.map_or(0, SaveType::size)),
+    };
+
+    writer.write_all(&header)?;
+    writer.write_all(options.ipl3.get_ipl())?;
+    writer.write_all(&program)?;
+    writer.write_all(&fs)?;
+
+    let mut end = contents;
+    for (offset, data) in embeds {
+        write_padding(writer, offset - end, options.pad_byte)?;
+        writer.write_all(data)?;
+        end = offset + data.len();
+    }
+
+    write_padding(writer, layout.padding.length, options.pad_byte)?;
+    write_padding(writer, layout.save.length, 0)?;
+
+    Ok(RomInfo {
+        crcs,
+        entry_point,
+        size: layout.save.offset + layout.save.length,
+        layout,
+    })
+}
+
+/// Places embedded files at their offsets, for a ROM whose contents end at
+/// `start`. Returns each file in order of its offset, and the offset where the
+/// last one ends. The gaps before each file are left to the caller to pad.
+fn embed_contents(
+    start: usize,
+    embeds: &[(usize, Vec<u8>)],
+) -> Result<(Vec<(usize, &[u8])>, usize), BuildError> {
+    let mut embeds: Vec<_> = embeds
+        .iter()
+        .map(|(offset, data)| (*offset, &data[..]))
+        .collect();
+    embeds.sort_by_key(|(offset, _)| *offset);
+
+    let mut end = start;
+    for &(offset, data) in &embeds {
+        if offset < end {
+            return Err(BuildError::EmbedOverlapError(offset, end));
+        }
+
+        end = offset + data.len();
+    }
+
+    Ok((embeds, end))
+}
+
+/// Writes `length` bytes of `pad_byte` in chunks, the padding can be as large
+/// as the ROM itself.
+fn write_padding<W: Write + ?Sized>(writer: &mut W, length: usize, pad_byte: u8) -> io::Result<()> {
+    let mut remaining = length;
+    let padding = [pad_byte; 64 * 1024];
+    while remaining > 0 {
+        let size = cmp::min(remaining, padding.len());
+        writer.write_all(&padding[..size])?;
+        remaining -= size;
+    }
+
+    Ok(())
+}
+
+/// Pads the program and aligns the file system, ready to be placed in a ROM.
+fn pad_contents(
+    mut program: Vec<u8>,
+    fs: Option<Vec<u8>>,
+    options: &RomOptions,
+) -> (Vec<u8>, Vec<u8>) {
+    let mut fs = fs.unwrap_or_default();
+
+    pad_program(&mut program, options.pad_byte);
+    if let Some(alignment) = options.fs_align.filter(|_| !fs.is_empty()) {
+        // The program already fills the CRC window, so the padding comes after
+        // the checksummed region
+        let offset = HEADER_SIZE + IPL_SIZE;
+        let alignment = alignment - 1;
+        let length = ((offset + program.len() + alignment) & !alignment) - offset;
+
+        program.resize(length, options.pad_byte);
+    }
+    align_to(&mut fs, std::mem::size_of::<u32>(), options.pad_byte);
+
+    (program, fs)
+}
+
+/// Creates a ROM image file from `args`, returning a summary of the written
+/// image. See [`stream_rom`].
+fn create_rom_image(
+    path: PathBuf,
+    args: &BuildArgs,
+    crate_name: &str,
+    entry_point: u32,
+    program: Vec<u8>,
+    fs: Option<Vec<u8>>,
+    verbose: usize,
+) -> Result<RomInfo, BuildError> {
+    let ipl3 = args.get_ipl3().unwrap();
+    let name = args.name.as_deref().unwrap_or(crate_name);
+    let mut options = RomOptions::new(name, ipl3.clone());
+    if let Some(region) = get_region(args, ipl3) {
+        options.region = region;
+    }
+    options.save = args.append_empty_save;
+    if args.deterministic_cart_id_from_name {
+        options.cart_id = cart_id_from_name(crate_name);
+    }
+    if let Some(clock_rate) = args.clock_rate {
+        options.header.clock_rate = clock_rate;
+    }
+    options.fs_align = args.fs_align;
+    options.pad_to = args.pad_to;
+    options.pad_byte = args.pad_byte.unwrap_or(PAD_BYTE);
+    options.max_program_size = args.max_program_size.unwrap_or(PROGRAM_SIZE);
+    for (offset, embed_path) in &args.embed {
+        let data = std::fs::read(embed_path)
+            .map_err(|_| BuildError::EmbedReadError(embed_path.to_owned()))?;
+        options.embeds.push((*offset, data));
+    }
+
+    // Check the size before creating the file, padding again while streaming
+    // changes nothing
+    let (program, fs) = pad_contents(program, fs, &options);
+    let contents = HEADER_SIZE + IPL_SIZE + program.len() + fs.len();
+    let (_, length) = embed_contents(contents, &options.embeds)?;
+    rom_size(length, options.pad_to)?;
+
+    let start = Instant::now();
+    let header = N64Header::new(entry_point, &program, &fs, &options);
+    print_phase_time(verbose, "checksum", start);
+
+    // The size was checked above, and the sink never fails
+    if args.dry_run {
+        let info = write_contents(&mut io::sink(), header, program, fs, &options);
+
+        return Ok(info.unwrap());
+    }
+
+    let start = Instant::now();
+    let mut info = None;
+    write_rom(&path, |writer| {
+        info = Some(write_contents(writer, header, program, fs, &options)?);
+
+        Ok(())
+    })?;
+    print_phase_time(verbose, "ROM image", start);
+
+    Ok(info.unwrap())
+}
+
+/// Selects the header region code, an explicit `--region` takes precedence over
+/// `--region-from-cic`. Returns `None` to keep the default.
+fn get_region(args: &BuildArgs, ipl3: &IPL3) -> Option<u8> {
+    if args.region.is_some() {
+        args.region
+    } else if args.region_from_cic {
+        ipl3.region()
+    } else {
+        None
+    }
+}
+
+/// Writes the ROM image produced by `rom` to `path`, or to stdout when `path` is `-`.
+fn write_rom<F>(path: &Path, rom: F) -> Result<(), BuildError>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    use self::BuildError::*;
+
+    if path == Path::new("-") {
+        write_rom_to(io::stdout().lock(), rom).map_err(|_| StdoutError)
+    } else {
+        File::create(path)
+            .and_then(|file| write_rom_to(file, rom))
+            .map_err(|_| CreateFileError(path.to_string_lossy().to_string()))
+    }
+}
+
+fn write_rom_to<F>(writer: impl Write, rom: F) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let mut writer = BufWriter::new(writer);
+    rom(&mut writer)?;
+    writer.flush()
+}
+
+/// Stands in for a cargo build artifact when converting a prebuilt ELF. The
+/// file stem takes the place of the crate name.
+fn elf_artifact(filename: &str) -> Result<CargoArtifact, BuildError> {
+    use self::BuildError::*;
+
+    let name = Path::new(filename)
+        .file_stem()
+        .ok_or(EmptyFilenameError)?
+        .to_str()
+        .ok_or(FilenameEncodingError)?
+        .to_owned();
+
+    Ok(CargoArtifact {
+        executable: filename.to_owned(),
+        package_id: None,
+        target: CargoArtifactTarget { name },
+    })
+}
+
 fn get_output_filename(filename: &str) -> Result<PathBuf, BuildError> {
     use self::BuildError::*;
 
@@ -240,30 +1343,126 @@ fn get_output_filename(filename: &str) -> Result<PathBuf, BuildError> {
     Ok(path)
 }
 
+/// The hash file is named after the whole ROM file name, e.g. `hello.n64.sha256`.
+fn get_hash_filename(rom_path: &Path) -> PathBuf {
+    let mut path = rom_path.as_os_str().to_owned();
+    path.push(".sha256");
+
+    PathBuf::from(path)
+}
+
 fn get_runtime(start: Instant) -> String {
     let total = start.elapsed();
     format!("{}.{}s", total.as_secs(), total.subsec_millis())
 }
 
+/// Print the time taken by a build phase, at verbosity 1 and above.
+fn print_phase_time(verbose: usize, phase: &str, start: Instant) {
+    if verbose > 0 {
+        eprintln!(
+            "{:>12} {} in {}",
+            "Timing".green().bold(),
+            phase,
+            get_runtime(start)
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::ipl3::PROGRAM_SIZE;
-    use crate::{pad_program, pad_rom, PAD_BYTE};
+    use crate::cargo::SubcommandError;
+    use crate::cli::{ArgParseError, BuildArgs, FixCrcArgs};
+    use crate::header::{N64Header, HEADER_SIZE};
+    use crate::ipl3::{IPL3, IPL_SIZE, PROGRAM_SIZE};
+    use crate::test_util::scratch_dir;
+    use crate::{
+        build_message, build_rom, build_rom_from_elf_bytes, cart_id_from_name, check_assumed_cic,
+        check_entry_point, check_ipl3, check_name_length, check_rom_crcs, create_rom_image,
+        elf_artifact, exit_code, fix_crc, format_crcs, format_crcs_diff, get_filesystem,
+        get_hash_filename, get_region, hex, layout_manifest, pad_program, pad_rom, recompute_crcs,
+        scan_rom_crcs, stream_rom, write_hash, write_rom, write_rom_to, BuildError, RomInfo,
+        RomLayout, RomOptions, RomRegion, RunError, SaveType, PAD_BYTE,
+    };
+    use colored::Color;
+    use gumdrop::Options;
+    use std::io::{self, Write};
+    use std::path::Path;
 
     #[test]
     fn test_program_pad() {
         let mut program = Vec::new();
 
-        pad_program(&mut program);
+        pad_program(&mut program, PAD_BYTE);
 
         assert_eq!(vec![PAD_BYTE; PROGRAM_SIZE], program);
     }
 
+    #[test]
+    fn test_program_pad_larger_than_crc_window() {
+        let mut program: Vec<u8> = (0..PROGRAM_SIZE + 1024).map(|i| i as u8).collect();
+        let expected = program.clone();
+
+        pad_program(&mut program, PAD_BYTE);
+
+        assert_eq!(expected, program);
+
+        // Only the first `PROGRAM_SIZE` bytes are covered by the CRCs
+        let ipl3 = IPL3::Cic6102([0; IPL_SIZE]);
+        assert_eq!(
+            ipl3.compute_crcs(&program, &[]),
+            ipl3.compute_crcs(&program[..PROGRAM_SIZE], &[]),
+        );
+    }
+
+    #[test]
+    fn test_pad_to() {
+        let options = RomOptions {
+            pad_to: Some(8 * 1024 * 1024),
+            ..RomOptions::new("pad-to", IPL3::Cic6102([0; IPL_SIZE]))
+        };
+        let rom = build_rom(0x8000_0400, vec![0; 64], None, options.clone()).unwrap();
+
+        assert_eq!(rom.len(), 8 * 1024 * 1024);
+        assert!(rom[0x1000 + PROGRAM_SIZE..].iter().all(|&b| b == PAD_BYTE));
+
+        let mut streamed = Vec::new();
+        stream_rom(&mut streamed, 0x8000_0400, vec![0; 64], None, &options).unwrap();
+        assert!(rom == streamed);
+
+        let mut rom = vec![0; 4 * 1024 * 1024 + 1];
+        assert!(matches!(
+            pad_rom(&mut rom, Some(4 * 1024 * 1024), PAD_BYTE),
+            Err(BuildError::PadToError(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_pad_byte() {
+        let options = RomOptions {
+            pad_byte: 0x00,
+            ..RomOptions::new("pad-byte", IPL3::Cic6102([0; IPL_SIZE]))
+        };
+        let rom = build_rom(0x8000_0400, vec![0x42; 6], Some(vec![0x55; 3]), options).unwrap();
+
+        assert_eq!(
+            &rom[0x1000..0x1008],
+            &[0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0, 0]
+        );
+        assert_eq!(
+            &rom[0x1000 + PROGRAM_SIZE..0x1000 + PROGRAM_SIZE + 5],
+            &[0x55, 0x55, 0x55, 0, 0]
+        );
+        assert!(rom[0x1000 + PROGRAM_SIZE + 4..].iter().all(|&b| b == 0));
+
+        let (_, stored, computed) = check_rom_crcs(&rom).unwrap();
+        assert_eq!(stored, computed);
+    }
+
     #[test]
     fn test_rom_pad_power_of_two() {
         let mut rom = Vec::new();
 
-        pad_rom(&mut rom);
+        pad_rom(&mut rom, None, PAD_BYTE).unwrap();
 
         assert_eq!(vec![PAD_BYTE; 2 * 1024 * 1024], rom);
     }
@@ -274,7 +1473,7 @@ mod tests {
         let expected_size = 12 * 1024 * 1024;
         let expected_padding = expected_size - rom.len();
 
-        pad_rom(&mut rom);
+        pad_rom(&mut rom, None, PAD_BYTE).unwrap();
 
         assert_eq!(rom.len(), expected_size);
         assert_eq!(
@@ -287,7 +1486,7 @@ mod tests {
     fn test_rom_pad_already_power_of_2() {
         let mut rom = vec![0; 2 * 1024 * 1024];
 
-        pad_rom(&mut rom);
+        pad_rom(&mut rom, None, PAD_BYTE).unwrap();
 
         assert_eq!(vec![0; 2 * 1024 * 1024], rom);
     }
@@ -296,8 +1495,537 @@ mod tests {
     fn test_rom_already_multiple_of() {
         let mut rom = vec![0; 12 * 1024 * 1024];
 
-        pad_rom(&mut rom);
+        pad_rom(&mut rom, None, PAD_BYTE).unwrap();
 
         assert_eq!(vec![0; 12 * 1024 * 1024], rom);
     }
+
+    #[test]
+    fn test_write_rom_stdout_matches_file() {
+        let rom: Vec<u8> = (0..2 * 1024 * 1024).map(|i| i as u8).collect();
+
+        let dir = scratch_dir("write-rom");
+        let path = dir.join("rom.n64");
+        write_rom(&path, |writer| writer.write_all(&rom)).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut stdout = Vec::new();
+        write_rom_to(&mut stdout, |writer| writer.write_all(&rom)).unwrap();
+
+        assert_eq!(file, stdout);
+    }
+
+    #[test]
+    fn test_build_rom() {
+        let ipl3 = IPL3::Cic6102([0xaa; IPL_SIZE]);
+        let options = RomOptions::new("hello", ipl3);
+
+        let rom = build_rom(0x8000_0400, vec![1, 2, 3, 4], None, options).unwrap();
+
+        assert_eq!(rom.len(), 2 * 1024 * 1024);
+        assert_eq!(&rom[0x20..0x34], b"hello               ");
+        assert_eq!(
+            &rom[HEADER_SIZE..HEADER_SIZE + IPL_SIZE],
+            &[0xaa; IPL_SIZE][..]
+        );
+        assert_eq!(&rom[0x1000..0x1004], &[1, 2, 3, 4]);
+        assert_eq!(
+            format_crcs(N64Header::from_bytes(&rom).unwrap().crcs()),
+            "CRC1: 0x0731ac1d CRC2: 0xf51ce58f"
+        );
+    }
+
+    #[test]
+    fn test_build_rom_from_elf_bytes() {
+        use crate::elf::fixtures::{build_elf, Section};
+        use goblin::elf::header::EM_MIPS;
+        use goblin::elf::section_header::{SHF_ALLOC, SHF_EXECINSTR};
+
+        let boot = Section {
+            name: ".boot",
+            addr: 0x8000_0400,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            data: &[1, 2, 3, 4, 5, 6, 7, 8],
+            nobits: false,
+        };
+        let elf = build_elf(EM_MIPS, false, 0x8000_0400, &[boot]);
+        let options = RomOptions::new("elf", IPL3::Cic6102([0; IPL_SIZE]));
+
+        let rom = build_rom_from_elf_bytes(&elf, options.clone()).unwrap();
+
+        assert!(
+            rom == build_rom(
+                0x8000_0400,
+                vec![1, 2, 3, 4, 5, 6, 7, 8],
+                None,
+                options.clone()
+            )
+            .unwrap()
+        );
+        assert_eq!(&rom[0x08..0x0c], &[0x80, 0x00, 0x04, 0x00]);
+        assert_eq!(&rom[0x1000..0x1008], &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let options = RomOptions {
+            max_program_size: 4,
+            ..options
+        };
+        assert!(matches!(
+            build_rom_from_elf_bytes(&elf, options),
+            Err(BuildError::ProgramTooBigError(4))
+        ));
+    }
+
+    #[test]
+    fn test_fs_align() {
+        let program = vec![0x42; PROGRAM_SIZE + 3];
+        let fs = vec![0x55; 4];
+        let mut options = RomOptions::new("align", IPL3::Cic6102([0; IPL_SIZE]));
+        options.fs_align = Some(64 * 1024);
+
+        let rom = build_rom(
+            0x8000_0400,
+            program.clone(),
+            Some(fs.clone()),
+            options.clone(),
+        )
+        .unwrap();
+        let mut streamed = Vec::new();
+        let info = stream_rom(&mut streamed, 0x8000_0400, program, Some(fs), &options).unwrap();
+        let fs_offset = info.layout.fs.offset;
+
+        assert_eq!(fs_offset, 0x11_0000);
+        assert_eq!(fs_offset % (64 * 1024), 0);
+        assert_eq!(
+            &rom[fs_offset - 1..fs_offset + 4],
+            &[0xff, 0x55, 0x55, 0x55, 0x55]
+        );
+    }
+
+    #[test]
+    fn test_stream_rom_matches_build_rom() {
+        let ipl3 = IPL3::Cic6102([0xaa; IPL_SIZE]);
+        let options = RomOptions::new("hello", ipl3);
+        let program: Vec<u8> = (0..PROGRAM_SIZE + 3).map(|i| i as u8).collect();
+        let fs = vec![0x55; 4 * 1024 * 1024 + 1];
+
+        let rom = build_rom(
+            0x8000_0400,
+            program.clone(),
+            Some(fs.clone()),
+            options.clone(),
+        )
+        .unwrap();
+        let mut streamed = Vec::new();
+        let info = stream_rom(&mut streamed, 0x8000_0400, program, Some(fs), &options).unwrap();
+
+        assert_eq!(rom.len(), 8 * 1024 * 1024);
+        assert!(rom == streamed);
+        assert_eq!(info.size, rom.len());
+        assert_eq!(info.entry_point, 0x8000_0400);
+        assert_eq!(info.crcs, N64Header::from_bytes(&rom).unwrap().crcs());
+    }
+
+    #[test]
+    fn test_embed() {
+        let mut options = RomOptions::new("embed", IPL3::Cic6102([0xaa; IPL_SIZE]));
+        options.embeds = vec![(0x20_0000, vec![2; 4]), (0x18_0000, vec![1; 4])];
+
+        let rom = build_rom(
+            0x8000_0400,
+            vec![0; 64],
+            Some(vec![0x55; 4]),
+            options.clone(),
+        );
+        let rom = rom.unwrap();
+        let mut streamed = Vec::new();
+        let info = stream_rom(
+            &mut streamed,
+            0x8000_0400,
+            vec![0; 64],
+            Some(vec![0x55; 4]),
+            &options,
+        )
+        .unwrap();
+
+        assert!(rom == streamed);
+        assert_eq!(rom.len(), 4 * 1024 * 1024);
+        assert_eq!(
+            &rom[0x10_1000..0x10_1005],
+            &[0x55, 0x55, 0x55, 0x55, PAD_BYTE]
+        );
+        assert_eq!(&rom[0x18_0000..0x18_0005], &[1, 1, 1, 1, PAD_BYTE]);
+        assert_eq!(&rom[0x20_0000..0x20_0005], &[2, 2, 2, 2, PAD_BYTE]);
+        assert_eq!(info.layout.embeds.offset, 0x10_1004);
+        assert_eq!(info.layout.embeds.length, 0x20_0004 - 0x10_1004);
+
+        // Embedded files are outside of the CRC window
+        let (_, stored, computed) = check_rom_crcs(&rom).unwrap();
+        assert_eq!(stored, computed);
+
+        options.embeds = vec![(0x10_1000, vec![1; 4])];
+        assert!(matches!(
+            build_rom(0x8000_0400, vec![0; 64], Some(vec![0x55; 4]), options),
+            Err(BuildError::EmbedOverlapError(0x10_1000, 0x10_1004))
+        ));
+    }
+
+    #[test]
+    fn test_append_empty_save() {
+        let ipl3 = IPL3::Cic6102([0xaa; IPL_SIZE]);
+        let program = vec![1, 2, 3, 4];
+        let rom = build_rom(
+            0x8000_0400,
+            program.clone(),
+            None,
+            RomOptions::new("save", ipl3.clone()),
+        )
+        .unwrap();
+
+        for save in [
+            SaveType::Eeprom4k,
+            SaveType::Eeprom16k,
+            SaveType::Sram,
+            SaveType::Sram768k,
+            SaveType::FlashRam,
+        ] {
+            let mut options = RomOptions::new("save", ipl3.clone());
+            options.save = Some(save);
+
+            let with_save = build_rom(0x8000_0400, program.clone(), None, options).unwrap();
+
+            // The header, including the CRCs, is unaffected
+            assert_eq!(with_save.len(), rom.len() + save.size());
+            assert!(with_save[..rom.len()] == rom[..]);
+            assert!(with_save[rom.len()..].iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn test_region_from_cic() {
+        let args = BuildArgs::parse_args_default(&["--region-from-cic"]).unwrap();
+
+        assert_eq!(get_region(&args, &IPL3::Cic7102([0; IPL_SIZE])), Some(b'P'));
+        assert_eq!(get_region(&args, &IPL3::Unknown([0; IPL_SIZE])), None);
+    }
+
+    #[test]
+    fn test_region_overrides_cic() {
+        let args = BuildArgs::parse_args_default(&["--region", "J", "--region-from-cic"]).unwrap();
+        assert_eq!(get_region(&args, &IPL3::Cic7102([0; IPL_SIZE])), Some(b'J'));
+
+        let args = BuildArgs::parse_args_default::<&str>(&[]).unwrap();
+        assert_eq!(get_region(&args, &IPL3::Cic7102([0; IPL_SIZE])), None);
+    }
+
+    #[test]
+    fn test_fix_crc() {
+        let options = RomOptions::new("fix-crc", IPL3::Cic6103([0; IPL_SIZE]));
+        let mut rom = build_rom(0x8000_0400, vec![0; 64], None, options).unwrap();
+        rom[0x1000] = 0x42;
+
+        let dir = scratch_dir("fix-crc");
+        let path = dir.join("rom.n64");
+        std::fs::write(&path, &rom).unwrap();
+
+        let rom_arg = Some(path.to_string_lossy().to_string());
+        fix_crc(FixCrcArgs { rom: rom_arg }).unwrap();
+        let fixed = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (_, stored, computed) = check_rom_crcs(&fixed).unwrap();
+        assert_ne!(&rom[0x10..0x18], &fixed[0x10..0x18]);
+        assert_eq!(stored, computed);
+        assert_eq!(&rom[..0x10], &fixed[..0x10]);
+        assert_eq!(&rom[0x18..], &fixed[0x18..]);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let code = |error: BuildError| RunError::from(error).exit_code();
+
+        assert_eq!(
+            RunError::from(ArgParseError::MissingIPL3Value).exit_code(),
+            2
+        );
+        assert_eq!(code(SubcommandError::NoArtifact.into()), 3);
+        assert_eq!(code(BuildError::EntryPointError(String::new())), 4);
+        assert_eq!(code(BuildError::ProgramTooBigError(PROGRAM_SIZE)), 5);
+        assert_eq!(code(BuildError::PadToError(2, 1)), 5);
+        assert_eq!(code(BuildError::StdoutError), 1);
+        assert_eq!(exit_code(&RunError::from(BuildError::PadToError(2, 1))), 5);
+    }
+
+    #[test]
+    fn test_format_crcs_diff() {
+        let crcs = (0x1234_5678, 0x9abc_def0);
+        assert_eq!(format_crcs_diff(crcs, crcs, Color::Red), format_crcs(crcs));
+
+        let diff = format_crcs_diff(crcs, (0x1234_5678, 0), Color::Red);
+        assert!(diff.starts_with("CRC1: 0x12345678 CRC2: "), "{}", diff);
+        assert!(diff.contains("0x9abcdef0"), "{}", diff);
+    }
+
+    #[test]
+    fn test_recompute_crcs() {
+        let options = RomOptions::new("recompute", IPL3::Unknown([0; IPL_SIZE]));
+        let rom = build_rom(0x8000_0400, vec![0x42; 64], None, options).unwrap();
+
+        let mut patched = rom.clone();
+        patched[0x10..0x18].fill(0);
+        let crcs = recompute_crcs(&mut patched).unwrap();
+
+        assert_eq!(patched, rom);
+        assert_eq!(crcs, N64Header::from_bytes(&rom).unwrap().crcs());
+        assert!(recompute_crcs(&mut [0; 0x100]).is_err());
+    }
+
+    #[test]
+    fn test_crc_scan() {
+        // The IPL bytes are zeroed, so the CIC can only be identified by its CRCs
+        let options = RomOptions::new("crc-scan", IPL3::Cic6103([0; IPL_SIZE]));
+        let rom = build_rom(0x8000_0400, vec![0x42; 64], None, options).unwrap();
+
+        let matches = scan_rom_crcs(&rom).unwrap();
+
+        assert_eq!(check_rom_crcs(&rom).unwrap().0.to_string(), "Unknown");
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].is_cic("6103"));
+    }
+
+    #[test]
+    fn test_elf_artifact() {
+        let artifact = elf_artifact("target/prebuilt/hello.elf").unwrap();
+
+        assert_eq!(artifact.executable, "target/prebuilt/hello.elf");
+        assert_eq!(artifact.target.name, "hello");
+        assert!(elf_artifact("").is_err());
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let ipl3 = IPL3::Cic6102([0; IPL_SIZE]);
+        let mut args = BuildArgs::parse_args_default(&["--dry-run"]).unwrap();
+        args.ipl3 = Some(ipl3.clone());
+
+        let dir = scratch_dir("dry-run");
+        let path = dir.join("rom.n64");
+
+        let program = vec![0x12; 64];
+        let info = create_rom_image(
+            path.clone(),
+            &args,
+            "dry run",
+            0x8000_0400,
+            program.clone(),
+            None,
+            0,
+        )
+        .unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rom = build_rom(0x8000_0400, program, None, RomOptions::new("dry run", ipl3)).unwrap();
+        assert_eq!(info.size, rom.len());
+        assert_eq!(info.crcs, N64Header::from_bytes(&rom).unwrap().crcs());
+    }
+
+    #[test]
+    fn test_no_fs() {
+        let args = BuildArgs::parse_args_default(&["--fs", "missing", "--fs-image", "missing.img"])
+            .unwrap();
+        assert!(get_filesystem(&args, true).is_err());
+
+        let args = BuildArgs::parse_args_default(&[
+            "--fs",
+            "missing",
+            "--fs-image",
+            "missing.img",
+            "--no-fs",
+        ])
+        .unwrap();
+        assert_eq!(get_filesystem(&args, true).unwrap(), None);
+    }
+
+    #[test]
+    fn test_fs_image_appended_verbatim() {
+        let dir = scratch_dir("fs-image");
+        std::fs::create_dir(dir.join("fs")).unwrap();
+        std::fs::write(dir.join("fs").join("data.bin"), [7; 1000]).unwrap();
+
+        let fs_path = dir.join("fs").to_string_lossy().to_string();
+        let args = BuildArgs::parse_args_default(&["--fs", &fs_path]).unwrap();
+        let from_dir = get_filesystem(&args, true).unwrap().unwrap();
+
+        let image_path = dir.join("fs.img");
+        std::fs::write(&image_path, &from_dir).unwrap();
+        let image_path = image_path.to_string_lossy().to_string();
+        let args = BuildArgs::parse_args_default(&["--fs-image", &image_path]).unwrap();
+        let from_image = get_filesystem(&args, true).unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let ipl3 = IPL3::Cic6102([0; IPL_SIZE]);
+        let build = |fs| {
+            build_rom(
+                0x8000_0400,
+                vec![],
+                Some(fs),
+                RomOptions::new("fs", ipl3.clone()),
+            )
+        };
+        let rom = build(from_image).unwrap();
+
+        // The ROM, including its CRCs, matches the directory-built one
+        assert!(rom == build(from_dir.clone()).unwrap());
+        let fs_offset = 0x1000 + PROGRAM_SIZE;
+        assert!(rom[fs_offset..fs_offset + from_dir.len()] == from_dir[..]);
+    }
+
+    #[test]
+    fn test_write_hash() {
+        let dir = scratch_dir("hash");
+        let rom_path = dir.join("hello.n64");
+        let hash_path = get_hash_filename(&rom_path);
+        assert_eq!(hash_path, dir.join("hello.n64.sha256"));
+        std::fs::write(&rom_path, b"abc").unwrap();
+
+        write_hash(&rom_path, &hash_path).unwrap();
+        let contents = std::fs::read_to_string(&hash_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            contents,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  hello.n64\n"
+        );
+        assert_eq!(hex(&[0x00, 0x0f, 0xa5]), "000fa5");
+    }
+
+    #[test]
+    fn test_non_ascii_name() {
+        let options = RomOptions::new("game \u{1f3ae}", IPL3::Cic6102([0; IPL_SIZE]));
+        let result = stream_rom(&mut io::sink(), 0x8000_0400, vec![0; 64], None, &options);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        match build_rom(0x8000_0400, vec![0; 64], None, options) {
+            Err(BuildError::NameEncodingError(name, c)) => {
+                assert_eq!(name, "game \u{1f3ae}");
+                assert_eq!(c, '\u{1f3ae}');
+            }
+            result => panic!("Unexpected result: {:?}", result.map(|rom| rom.len())),
+        }
+
+        let options = RomOptions::new("Hello, N64!", IPL3::Cic6102([0; IPL_SIZE]));
+        assert!(build_rom(0x8000_0400, vec![0; 64], None, options).is_ok());
+    }
+
+    #[test]
+    fn test_cart_id_from_name() {
+        assert_eq!(
+            RomOptions::new("hello", IPL3::Unknown([0; IPL_SIZE])).cart_id,
+            *b"KW"
+        );
+        assert_eq!(cart_id_from_name("hello"), *b"OA");
+
+        for name in ["", "hello", "hello-ipl3font", "rrt0", "\u{1f3ae}"] {
+            let cart_id = cart_id_from_name(name);
+            assert!(cart_id.iter().all(u8::is_ascii_uppercase), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_check_entry_point() {
+        let cic6102 = IPL3::Cic6102([0; IPL_SIZE]);
+        let cic6103 = IPL3::Cic6103([0; IPL_SIZE]);
+        let cic6106 = IPL3::Cic6106([0; IPL_SIZE]);
+
+        assert_eq!(check_entry_point(0x8000_0400, &cic6102), None);
+        assert_eq!(check_entry_point(0x8000_0400, &cic6103), None);
+        assert_eq!(check_entry_point(0x8000_0400, &cic6106), None);
+        assert_eq!(check_entry_point(0x8010_0400, &cic6102), None);
+        assert!(check_entry_point(0x8010_0400, &cic6103).is_some());
+        assert!(check_entry_point(0x8020_0400, &cic6106).is_some());
+        assert!(check_entry_point(0x0000_0400, &cic6102).is_some());
+        assert!(check_entry_point(0x807f_0000, &cic6102).is_none());
+        assert!(check_entry_point(0x8070_0000, &cic6106).is_some());
+    }
+
+    #[test]
+    fn test_check_assumed_cic() {
+        assert_eq!(check_assumed_cic(&IPL3::Unknown([0; IPL_SIZE])), None);
+
+        let message = check_assumed_cic(&IPL3::Cic6105([0; IPL_SIZE])).unwrap();
+        assert!(message.contains("CIC-NUS-6105"), "{}", message);
+    }
+
+    #[test]
+    fn test_check_name_length() {
+        assert_eq!(check_name_length("hello-ipl3font"), None);
+        assert_eq!(check_name_length("twenty characters!!!"), None);
+        assert_eq!(
+            check_name_length("a-rather-long-crate-name").unwrap(),
+            "Program name `a-rather-long-crate-name` is longer than 20 bytes and will be \
+             truncated to `a-rather-long-crate-`"
+        );
+    }
+
+    #[test]
+    fn test_check_ipl3() {
+        assert_eq!(check_ipl3(&IPL3::Cic6102([0; IPL_SIZE])), None);
+
+        let message = check_ipl3(&IPL3::Unknown([0; IPL_SIZE])).unwrap();
+        let crc = format!("{:08x}", crc32fast::hash(&[0; IPL_SIZE]));
+        assert!(message.contains(&crc), "{}", message);
+    }
+
+    #[test]
+    fn test_layout_manifest() {
+        let mut options = RomOptions::new("layout", IPL3::Cic6102([0; IPL_SIZE]));
+        options.save = Some(SaveType::Eeprom4k);
+        let mut rom = Vec::new();
+        let info = stream_rom(
+            &mut rom,
+            0x8000_0400,
+            vec![0; 64],
+            Some(vec![0; 6]),
+            &options,
+        )
+        .unwrap();
+
+        let region = |offset, length| RomRegion { offset, length };
+        assert_eq!(
+            info.layout,
+            RomLayout {
+                header: region(0, 0x40),
+                ipl3: region(0x40, 0xfc0),
+                program: region(0x1000, PROGRAM_SIZE),
+                fs: region(0x1000 + PROGRAM_SIZE, 8),
+                embeds: region(0x1000 + PROGRAM_SIZE + 8, 0),
+                padding: region(0x1000 + PROGRAM_SIZE + 8, 0x10_0000 - 0x1000 - 8),
+                save: region(0x20_0000, 512),
+            }
+        );
+        assert_eq!(info.size, rom.len());
+        assert_eq!(
+            layout_manifest(&info.layout)["padding"].to_string(),
+            r#"{"len":1044472,"off":1052680}"#
+        );
+    }
+
+    #[test]
+    fn test_build_message() {
+        let info = RomInfo {
+            crcs: (0x0731_ac1d, 0xf51c_e58f),
+            entry_point: 0x8000_0400,
+            size: 2 * 1024 * 1024,
+            layout: RomLayout::default(),
+        };
+        let ipl3 = IPL3::Cic6102([0; IPL_SIZE]);
+
+        let message = build_message(Path::new("hello.n64"), &info, &ipl3);
+
+        assert_eq!(
+            message.to_string(),
+            r#"{"cic":"CIC-NUS-6102","crc1":120695837,"crc2":4112311695,"rom":"hello.n64","size":2097152}"#
+        );
+    }
 }
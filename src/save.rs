@@ -0,0 +1,87 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Cartridge save types, used to size an empty save region appended to the ROM.
+///
+/// The save region starts right after the padded ROM image, so it is never
+/// covered by the CRCs in the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveType {
+    /// 4 Kbit EEPROM
+    Eeprom4k,
+
+    /// 16 Kbit EEPROM
+    Eeprom16k,
+
+    /// 256 Kbit SRAM
+    Sram,
+
+    /// 768 Kbit SRAM
+    Sram768k,
+
+    /// 1 Mbit FlashRAM
+    FlashRam,
+}
+
+impl SaveType {
+    /// Size of the save region in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            SaveType::Eeprom4k => 512,
+            SaveType::Eeprom16k => 2 * 1024,
+            SaveType::Sram => 32 * 1024,
+            SaveType::Sram768k => 96 * 1024,
+            SaveType::FlashRam => 128 * 1024,
+        }
+    }
+}
+
+impl fmt::Display for SaveType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SaveType::Eeprom4k => "eeprom4k",
+            SaveType::Eeprom16k => "eeprom16k",
+            SaveType::Sram => "sram",
+            SaveType::Sram768k => "sram768k",
+            SaveType::FlashRam => "flashram",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for SaveType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eeprom4k" => Ok(SaveType::Eeprom4k),
+            "eeprom16k" => Ok(SaveType::Eeprom16k),
+            "sram" => Ok(SaveType::Sram),
+            "sram768k" => Ok(SaveType::Sram768k),
+            "flashram" => Ok(SaveType::FlashRam),
+            _ => Err(format!(
+                "Expected one of eeprom4k, eeprom16k, sram, sram768k, flashram, found `{}`",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SaveType;
+
+    #[test]
+    fn test_parse_save_type() {
+        for save in [
+            SaveType::Eeprom4k,
+            SaveType::Eeprom16k,
+            SaveType::Sram,
+            SaveType::Sram768k,
+            SaveType::FlashRam,
+        ] {
+            assert_eq!(save.to_string().parse(), Ok(save));
+        }
+        assert!("mempak".parse::<SaveType>().is_err());
+    }
+}
@@ -0,0 +1,14 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Create an empty scratch directory that is unique to the calling test
+pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+    let mut path = env::temp_dir();
+    path.push(format!("cargo-n64-{}-{}", std::process::id(), name));
+
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+
+    path
+}
@@ -0,0 +1,68 @@
+//! End-to-end build of the `hello-ipl3font` example from the `rrt0` repository.
+//!
+//! This needs the pinned nightly toolchain with the `rust-src` component and a
+//! checkout of https://github.com/rust-console/rrt0. The test is skipped unless
+//! `RRT0_PATH` points at that checkout.
+
+use cargo_n64::{IPL3, IPL_SIZE};
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// An all-zero bootcode. It is not copyrighted, and is detected as `Unknown`.
+const IPL3_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ipl3-zeros.bin");
+
+fn cargo_n64(dir: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_cargo-n64"))
+        .arg("n64")
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .expect("Unable to run cargo-n64");
+
+    assert!(status.success(), "`cargo n64 {}` failed", args.join(" "));
+}
+
+fn read_word(rom: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(rom[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn build_hello_ipl3font() {
+    let rrt0 = match env::var_os("RRT0_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("Skipping: set RRT0_PATH to a checkout of rust-console/rrt0");
+            return;
+        }
+    };
+
+    let args = [
+        "build",
+        "--ipl3",
+        IPL3_FIXTURE,
+        "--",
+        "--package",
+        "hello-ipl3font",
+    ];
+    cargo_n64(&rrt0, &args);
+
+    let path = rrt0.join("target/mips-nintendo64-none/release/hello-ipl3font.n64");
+    let rom = fs::read(&path).expect("ROM image was not written");
+
+    // Program name defaults to the crate name
+    assert_eq!(&rom[0x20..0x34], b"hello-ipl3font      ");
+
+    // The entry point must be in KSEG0, after the exception vectors
+    let entry_point = read_word(&rom, 0x08);
+    assert!((0x8000_0400..0x8080_0000).contains(&entry_point));
+
+    // The header CRCs must cover the program exactly as written
+    let ipl3 = IPL3::Unknown([0; IPL_SIZE]);
+    let program = &rom[0x1000..];
+    let (crc1, crc2) = ipl3.compute_crcs_reader(program, program.len()).unwrap();
+    assert_eq!(read_word(&rom, 0x10), crc1);
+    assert_eq!(read_word(&rom, 0x14), crc2);
+}